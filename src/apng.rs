@@ -0,0 +1,287 @@
+// Built-in APNG decoding and animated WebP encoding, no external tools needed
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::jobs::Progress;
+use crate::playback;
+
+use image::RgbaImage;
+use image::imageops::{self, FilterType};
+use png::{BlendOp, ColorType, DisposeOp, Transformations};
+use webp_animation::{
+    AnimParams, Encoder, EncoderOptions, EncodingConfig, EncodingType, LossyEncodingConfig,
+};
+
+/// A single fully composited frame, RGBA8 at the animation's canvas size.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub rgba: Vec<u8>,
+    pub delay_ms: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub width: u32,
+    pub height: u32,
+    /// Number of times to play the animation, 0 means loop forever.
+    pub loop_count: u32,
+    pub frames: Vec<Frame>,
+}
+
+impl Animation {
+    pub fn total_duration_ms(&self) -> u64 {
+        self.frames.iter().map(|f| f.delay_ms as u64).sum()
+    }
+
+    /// Scales every frame down, keeping the aspect ratio, so the canvas fits
+    /// `max_width`×`max_height` and is at most `factor` times its current size.
+    pub fn downscale(&mut self, max_width: Option<u32>, max_height: Option<u32>, factor: f32) {
+        let mut ratio = factor.min(1.0) as f64;
+        if let Some(max_width) = max_width {
+            ratio = ratio.min(max_width as f64 / self.width as f64);
+        }
+        if let Some(max_height) = max_height {
+            ratio = ratio.min(max_height as f64 / self.height as f64);
+        }
+        if ratio >= 1.0 {
+            return;
+        }
+
+        let width = ((self.width as f64 * ratio).round() as u32).max(1);
+        let height = ((self.height as f64 * ratio).round() as u32).max(1);
+        for frame in &mut self.frames {
+            let Some(image) =
+                RgbaImage::from_raw(self.width, self.height, std::mem::take(&mut frame.rgba))
+            else {
+                continue;
+            };
+            frame.rgba = imageops::resize(&image, width, height, FilterType::Triangle).into_raw();
+        }
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Drops frames so none is shown for less than `1000 / max_fps` ms. The
+    /// dropped frames' time goes to the frame before them, so timing is kept.
+    pub fn limit_fps(&mut self, max_fps: f32) {
+        if max_fps <= 0.0 {
+            return;
+        }
+        let min_delay = (1000.0 / max_fps) as u32;
+        let mut frames: Vec<Frame> = Vec::with_capacity(self.frames.len());
+        for frame in self.frames.drain(..) {
+            match frames.last_mut() {
+                Some(last) if last.delay_ms < min_delay => last.delay_ms += frame.delay_ms,
+                _ => frames.push(frame),
+            }
+        }
+        self.frames = frames;
+    }
+}
+
+pub fn decode_apng(path: &Path) -> Result<Animation, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+
+    let width = reader.info().width;
+    let height = reader.info().height;
+    let (num_frames, loop_count) = match reader.info().animation_control {
+        Some(actl) => (actl.num_frames, actl.num_plays),
+        None => (1, 0),
+    };
+    // Without an fcTL before IDAT the default image is only a fallback for
+    // decoders that don't understand APNG and isn't part of the animation.
+    let skip_default_image =
+        reader.info().animation_control.is_some() && reader.info().frame_control.is_none();
+
+    let mut canvas = vec![0u8; width as usize * height as usize * 4];
+    let mut buf = vec![0u8; reader.output_buffer_size().ok_or("PNG frame too large")?];
+    let mut frames: Vec<Frame> = Vec::with_capacity(num_frames as usize);
+
+    if skip_default_image {
+        reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+    }
+
+    for index in 0..num_frames {
+        let output = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+        let fctl = reader.info().frame_control.unwrap_or(png::FrameControl {
+            width,
+            height,
+            ..Default::default()
+        });
+
+        if fctl.x_offset + fctl.width > width || fctl.y_offset + fctl.height > height {
+            return Err(format!("APNG frame {} lies outside the canvas", index));
+        }
+
+        let sub = to_rgba(&buf, &output)?;
+
+        let mut dispose_op = fctl.dispose_op;
+        if index == 0 && dispose_op == DisposeOp::Previous {
+            dispose_op = DisposeOp::Background;
+        }
+        let saved = if dispose_op == DisposeOp::Previous {
+            Some(canvas.clone())
+        } else {
+            None
+        };
+
+        for row in 0..fctl.height as usize {
+            for col in 0..fctl.width as usize {
+                let src = (row * fctl.width as usize + col) * 4;
+                let dst = ((fctl.y_offset as usize + row) * width as usize
+                    + fctl.x_offset as usize
+                    + col)
+                    * 4;
+                match fctl.blend_op {
+                    BlendOp::Source => {
+                        canvas[dst..dst + 4].copy_from_slice(&sub[src..src + 4]);
+                    }
+                    BlendOp::Over => blend_over(&mut canvas[dst..dst + 4], &sub[src..src + 4]),
+                }
+            }
+        }
+
+        frames.push(Frame {
+            rgba: canvas.clone(),
+            delay_ms: delay_ms(fctl.delay_num, fctl.delay_den),
+        });
+
+        match dispose_op {
+            DisposeOp::None => {}
+            DisposeOp::Background => {
+                for row in 0..fctl.height as usize {
+                    let start = ((fctl.y_offset as usize + row) * width as usize
+                        + fctl.x_offset as usize)
+                        * 4;
+                    canvas[start..start + fctl.width as usize * 4].fill(0);
+                }
+            }
+            DisposeOp::Previous => {
+                if let Some(saved) = saved {
+                    canvas = saved;
+                }
+            }
+        }
+    }
+
+    if frames.is_empty() {
+        return Err("APNG contains no frames".to_string());
+    }
+
+    Ok(Animation {
+        width,
+        height,
+        loop_count,
+        frames,
+    })
+}
+
+/// Encodes `animation` as an animated WebP. `quality` (0-100) only applies to lossy encoding.
+/// Encoded frames are counted on `progress`, and encoding stops if it gets cancelled.
+pub fn encode_webp(
+    animation: &Animation,
+    output_path: &Path,
+    lossless: bool,
+    quality: f32,
+    progress: &Progress,
+) -> Result<(), String> {
+    let encoding_type = if lossless {
+        EncodingType::Lossless
+    } else {
+        EncodingType::Lossy(LossyEncodingConfig::default())
+    };
+    let options = EncoderOptions {
+        anim_params: AnimParams {
+            loop_count: animation.loop_count as i32,
+        },
+        encoding_config: Some(EncodingConfig {
+            encoding_type,
+            quality,
+            method: 4,
+        }),
+        ..Default::default()
+    };
+    let mut encoder = Encoder::new_with_options((animation.width, animation.height), options)
+        .map_err(|e| e.to_string())?;
+
+    let mut timestamp: i32 = 0;
+    for (index, frame) in animation.frames.iter().enumerate() {
+        if progress.is_cancelled() {
+            return Err("cancelled".to_string());
+        }
+        encoder
+            .add_frame(&frame.rgba, timestamp)
+            .map_err(|e| e.to_string())?;
+        timestamp += frame.delay_ms as i32;
+        progress.set_done(index as u64 + 1);
+    }
+    let webp_data = encoder.finalize(timestamp).map_err(|e| e.to_string())?;
+
+    std::fs::write(output_path, &*webp_data).map_err(|e| e.to_string())
+}
+
+/// Near-zero delays are shown the way GIFs are; see `playback::shown_delay`.
+fn delay_ms(num: u16, den: u16) -> u32 {
+    let den = if den == 0 { 100 } else { den as u32 };
+    let delay = Duration::from_millis((num as u32 * 1000 / den) as u64);
+    playback::shown_delay(delay).as_millis() as u32
+}
+
+fn to_rgba(buf: &[u8], output: &png::OutputInfo) -> Result<Vec<u8>, String> {
+    let pixels = output.width as usize * output.height as usize;
+    let mut rgba = Vec::with_capacity(pixels * 4);
+    for row in buf
+        .chunks_exact(output.line_size)
+        .take(output.height as usize)
+    {
+        match output.color_type {
+            ColorType::Rgba => rgba.extend_from_slice(&row[..output.width as usize * 4]),
+            ColorType::Rgb => {
+                for px in row[..output.width as usize * 3].chunks_exact(3) {
+                    rgba.extend_from_slice(&[px[0], px[1], px[2], 255]);
+                }
+            }
+            ColorType::GrayscaleAlpha => {
+                for px in row[..output.width as usize * 2].chunks_exact(2) {
+                    rgba.extend_from_slice(&[px[0], px[0], px[0], px[1]]);
+                }
+            }
+            ColorType::Grayscale => {
+                for &g in &row[..output.width as usize] {
+                    rgba.extend_from_slice(&[g, g, g, 255]);
+                }
+            }
+            ColorType::Indexed => {
+                return Err("Indexed PNG was not expanded".to_string());
+            }
+        }
+    }
+    Ok(rgba)
+}
+
+fn blend_over(dst: &mut [u8], src: &[u8]) {
+    let src_a = src[3] as u32;
+    if src_a == 255 {
+        dst.copy_from_slice(src);
+        return;
+    }
+    if src_a == 0 {
+        return;
+    }
+    let dst_a = dst[3] as u32;
+    let out_a = src_a * 255 + dst_a * (255 - src_a);
+    if out_a == 0 {
+        dst.fill(0);
+        return;
+    }
+    for c in 0..3 {
+        let value = (src[c] as u32 * src_a * 255 + dst[c] as u32 * dst_a * (255 - src_a)) / out_a;
+        dst[c] = value as u8;
+    }
+    dst[3] = (out_a / 255) as u8;
+}
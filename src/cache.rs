@@ -0,0 +1,169 @@
+// App-managed cache for converted media, keyed by a content hash of the input
+use crate::convert::{self, ConvertError, ConvertOptions, VideoOptions};
+use crate::jobs::Progress;
+use crate::logging::log_to_file;
+use crate::paths;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tempfile::NamedTempFile;
+
+/// Unreferenced entries younger than this are kept, so a file that was just
+/// converted but not (yet) added to the library isn't collected straight away.
+pub const GC_GRACE_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Converted media lives with the app's data rather than in the OS cache
+/// directory: characters point straight at these files, so they must not be
+/// purged behind the library's back.
+pub fn cache_dir() -> PathBuf {
+    paths::data_dir().join("cache")
+}
+
+pub fn content_hash(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Returns the cached WebP for `input`, converting it first if it isn't cached yet.
+/// Each set of conversion options gets its own entry.
+pub fn cached_webp(
+    input: &Path,
+    options: &ConvertOptions,
+    progress: &Progress,
+) -> Result<PathBuf, ConvertError> {
+    let key = format!("-{}", options.cache_key());
+    cached_conversion(input, &key, |output| {
+        convert::convert_to_webp(input, output, options, progress).map(|_| ())
+    })
+}
+
+/// Like `cached_webp` for video clips.
+pub fn cached_video_webp(
+    input: &Path,
+    video: &VideoOptions,
+    options: &ConvertOptions,
+    progress: &Progress,
+) -> Result<PathBuf, ConvertError> {
+    let key = format!("-{}-{}", video.cache_key(), options.cache_key());
+    cached_conversion(input, &key, |output| {
+        convert::convert_video_to_webp(input, output, video, options, progress).map(|_| ())
+    })
+}
+
+fn cached_conversion(
+    input: &Path,
+    key: &str,
+    convert: impl FnOnce(&Path) -> Result<(), ConvertError>,
+) -> Result<PathBuf, ConvertError> {
+    let hash = content_hash(input)?;
+    let dir = cache_dir();
+    let output = dir.join(format!("{}{}.webp", hash, key));
+
+    if output.exists() {
+        log_to_file(&format!(
+            "cache: reusing {} for {}",
+            output.display(),
+            input.display()
+        ));
+        // Refresh the timestamp so garbage collection sees the entry as recently used.
+        if let Ok(file) = File::options().append(true).open(&output) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        return Ok(output);
+    }
+
+    fs::create_dir_all(&dir)?;
+    // Convert under a temporary name so an interrupted run never leaves a
+    // truncated file that would later be mistaken for a cache hit, and so
+    // imports of the same file running side by side don't write to one file.
+    // The extension stays, as ffmpeg picks the output format by it.
+    let partial = tempfile::Builder::new()
+        .suffix(".partial.webp")
+        .tempfile_in(&dir)?;
+    convert(partial.path())?;
+    partial.persist(&output).map_err(|e| e.error)?;
+    Ok(output)
+}
+
+/// Returns a copy of `input` in the cache with the given extension, for files
+/// whose name doesn't match their actual format.
+pub fn cached_copy(input: &Path, extension: &str) -> io::Result<PathBuf> {
+    let hash = content_hash(input)?;
+    let dir = cache_dir();
+    let output = dir.join(format!("{}.{}", hash, extension));
+
+    if !output.exists() {
+        fs::create_dir_all(&dir)?;
+        let partial = NamedTempFile::new_in(&dir)?;
+        fs::copy(input, partial.path())?;
+        partial.persist(&output).map_err(|e| e.error)?;
+    }
+    Ok(output)
+}
+
+/// Deletes `path` if it's a cache entry that isn't in `referenced` (see
+/// `profiles::referenced_paths`). Files outside the cache belong to the user
+/// and are never touched.
+pub fn remove_if_unreferenced(path: &Path, referenced: &HashSet<PathBuf>) -> bool {
+    if !path.starts_with(cache_dir()) || referenced.contains(path) {
+        return false;
+    }
+    match fs::remove_file(path) {
+        Ok(()) => true,
+        Err(e) => {
+            log_to_file(&format!(
+                "cache: failed to remove {}: {}",
+                path.display(),
+                e
+            ));
+            false
+        }
+    }
+}
+
+/// Removes cache entries that aren't in `referenced` and that haven't been
+/// used within `GC_GRACE_PERIOD`. Every profile shares the cache, so
+/// `referenced` has to cover all of them. Returns the number of files removed.
+pub fn collect_garbage(referenced: &HashSet<PathBuf>) -> usize {
+    let entries = match fs::read_dir(cache_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    let now = SystemTime::now();
+
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if referenced.contains(&path) {
+            continue;
+        }
+        let age = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        if age < GC_GRACE_PERIOD {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(e) => log_to_file(&format!(
+                "cache: failed to remove {}: {}",
+                path.display(),
+                e
+            )),
+        }
+    }
+
+    if removed > 0 {
+        log_to_file(&format!("cache: removed {} unused entries", removed));
+    }
+    removed
+}
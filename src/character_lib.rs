@@ -1,19 +1,61 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
-
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::cache;
+use crate::convert::{ConvertError, ConvertOptions, VideoOptions};
+use crate::jobs::Progress;
+use crate::layouts::SceneLayout;
+use crate::logging::log_to_file;
+use crate::media_info::{self, MediaInfo};
+use crate::migrations::{self, LIBRARY_VERSION};
+use crate::pack::PackImport;
+use crate::paths;
+use crate::persist::SaveTracker;
+use crate::playback::{PlaybackOptions, Speed};
+use crate::sniff::{MediaFormat, sniff_file};
+use crate::store::{self, FsStore, LibraryStore};
+use uuid::Uuid;
+
+/// A media file in the library with its metadata. What's on screen are its
+/// `instances`, each with its own window.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Character {
+    /// Stable identity; survives renames, moves and re-encodes of the file.
+    #[serde(default = "Uuid::new_v4")]
+    pub id: Uuid,
     pub name: String,
     pub path: String,
+    /// Always at least one.
+    #[serde(default)]
+    pub instances: Vec<Instance>,
+    #[serde(default)]
+    pub media_info: Option<MediaInfo>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// SHA-256 of the media file, used to spot duplicates on import and to
+    /// find the file again if it moves.
+    #[serde(default)]
+    pub content_hash: Option<String>,
+}
+
+/// One on-screen copy of a character: its own window, placement and playback.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Instance {
+    #[serde(default = "Uuid::new_v4")]
+    pub id: Uuid,
     #[serde(default)]
     pub enabled: bool,
     #[serde(default)]
-    pub speed: i64,
+    pub speed: Speed,
     #[serde(default = "default_scale")]
     pub scale: f32,
     #[serde(default)]
+    pub playback: PlaybackOptions,
+    #[serde(default)]
     pub window_pos: Option<[f32; 2]>,
     #[serde(default)]
     pub window_size: Option<[f32; 2]>,
@@ -23,288 +65,926 @@ fn default_scale() -> f32 {
     1.0
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct CharacterLibrary {
-    pub characters: Vec<Character>,
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            enabled: false,
+            speed: Speed::default(),
+            scale: default_scale(),
+            playback: PlaybackOptions::default(),
+            window_pos: None,
+            window_size: None,
+        }
+    }
 }
 
-impl CharacterLibrary {
-    pub fn load() -> Self {
-        let path = Self::get_library_path();
-        if path.exists() {
-            match fs::read_to_string(&path) {
-                Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
-                Err(_) => Self::default(),
-            }
-        } else {
-            Self::default()
-        }
+impl Character {
+    pub fn is_enabled(&self) -> bool {
+        self.instances.iter().any(|i| i.enabled)
     }
+}
+
+/// How far a new copy is placed from the one it was made from.
+const INSTANCE_OFFSET: f32 = 40.0;
 
-    pub fn save(&self) {
-        let path = Self::get_library_path();
-        if let Some(parent) = path.parent() {
-            let _ = fs::create_dir_all(parent);
+/// Per-import settings. `convert` applies to everything that gets converted,
+/// `video` only to video clips.
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    pub video: VideoOptions,
+    pub convert: ConvertOptions,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    FileMissing(String),
+    UnsupportedFormat(String),
+    DecodeFailed {
+        path: String,
+        reason: String,
+    },
+    /// The file is already in the library at `index`.
+    Duplicate {
+        index: usize,
+        name: String,
+    },
+    ConversionFailed(ConvertError),
+    Cancelled,
+}
+
+impl From<ConvertError> for ImportError {
+    fn from(e: ConvertError) -> Self {
+        match e {
+            ConvertError::Cancelled => ImportError::Cancelled,
+            e => ImportError::ConversionFailed(e),
         }
-        let content = serde_json::to_string_pretty(self).unwrap_or_default();
-        let _ = fs::write(&path, content);
     }
+}
 
-    pub fn convert_apng_to_webp(input_path: &str) -> Option<String> {
-        let path_buf = PathBuf::from(input_path);
-        let Some(parent) = path_buf.parent() else {
-            return None;
-        };
-        let stem = path_buf.file_stem()?.to_str()?;
-        let webp_name = format!("{}.webp", stem);
-        let webp_path = parent.join(&webp_name);
-        let webp_path_str = webp_path.to_str()?;
-
-        println!(
-            "Converting APNG to WebP: {} -> {}",
-            input_path, webp_path_str
-        );
-
-        match Self::convert_apng_to_webp_internal(input_path, webp_path_str) {
-            Ok(_) => {
-                println!("Conversion successful!");
-                Some(webp_path_str.to_string())
+/// A file that has been converted and probed, ready to be added with
+/// `CharacterLibrary::add_prepared`. Preparing is the slow part of an import
+/// and doesn't touch the library, so it can run on a worker thread.
+#[derive(Debug, Clone)]
+pub struct PreparedImport {
+    pub name: String,
+    pub path: String,
+    pub media_info: MediaInfo,
+    pub content_hash: Option<String>,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::FileMissing(path) => write!(f, "File not found: {}", path),
+            ImportError::UnsupportedFormat(path) => write!(f, "Unsupported file type: {}", path),
+            ImportError::DecodeFailed { path, reason } => {
+                write!(f, "Could not read {}: {}", path, reason)
             }
-            Err(e) => {
-                println!("Conversion failed: {}", e);
-                None
+            ImportError::Duplicate { name, .. } => {
+                write!(f, "\"{}\" is already in the library", name)
             }
+            ImportError::ConversionFailed(e) => write!(f, "Conversion failed: {}", e),
+            ImportError::Cancelled => write!(f, "Import cancelled"),
         }
     }
+}
 
-    fn find_command(name: &str) -> Option<String> {
-        let paths = [
-            format!("/opt/homebrew/bin/{}", name),
-            format!("/usr/local/bin/{}", name),
-            format!("/usr/bin/{}", name),
-        ];
+impl std::error::Error for ImportError {}
 
-        for path in &paths {
-            if std::path::Path::new(path).exists() {
-                return Some(path.clone());
-            }
-        }
+/// One character re-encoded by `CharacterLibrary::optimize`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OptimizedEntry {
+    pub id: Uuid,
+    pub name: String,
+    pub original_path: String,
+    pub new_path: String,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub media_info: MediaInfo,
+    pub content_hash: Option<String>,
+}
 
-        if let Ok(output) = std::process::Command::new("which").arg(name).output() {
-            if output.status.success() {
-                let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                if !path.is_empty() {
-                    return Some(path);
-                }
-            }
-        }
+#[derive(Debug, Clone, Default)]
+pub struct OptimizeReport {
+    pub entries: Vec<OptimizedEntry>,
+    /// Characters left alone, with the reason.
+    pub skipped: Vec<(String, String)>,
+}
 
-        None
+impl OptimizeReport {
+    pub fn bytes_before(&self) -> u64 {
+        self.entries.iter().map(|e| e.bytes_before).sum()
     }
 
-    fn convert_apng_to_webp_internal(input_path: &str, output_path: &str) -> Result<(), String> {
-        use std::fs;
-        use std::process::Command;
-        use tempfile::TempDir;
+    pub fn bytes_after(&self) -> u64 {
+        self.entries.iter().map(|e| e.bytes_after).sum()
+    }
+}
 
-        println!("Converting APNG to animated WebP...");
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CharacterLibrary {
+    /// Schema version, see `migrations`.
+    #[serde(default)]
+    pub version: u32,
+    pub characters: Vec<Character>,
+    #[serde(default)]
+    pub layouts: Vec<SceneLayout>,
+    /// Directory that character paths are stored relative to, `~` meaning the
+    /// home directory. `None` uses the data directory. Kept in the library so
+    /// it travels with it when synced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_root: Option<String>,
+    /// An applied optimization the user hasn't confirmed or reverted yet. Kept
+    /// in the library so its original files survive a restart in between.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending_optimization: Vec<OptimizedEntry>,
+    /// Set when the file on disk couldn't be loaded as-is, for the UI to show.
+    #[serde(skip)]
+    pub load_warning: Option<String>,
+    /// Set when saving after a change failed, for the UI to show.
+    #[serde(skip)]
+    pub save_error: Option<String>,
+    #[serde(skip)]
+    tracker: SaveTracker,
+    #[serde(skip, default = "default_store")]
+    store: Arc<dyn LibraryStore>,
+}
+
+pub const LIBRARY_FILE: &str = "library.json";
+
+/// The directory relative paths are resolved against, see `CharacterLibrary::media_root`.
+pub fn media_root_dir(media_root: Option<&str>) -> PathBuf {
+    match media_root {
+        Some("~") => paths::home_dir(),
+        Some(root) => match root.strip_prefix("~/") {
+            Some(rest) => paths::home_dir().join(rest),
+            None => PathBuf::from(root),
+        },
+        None => paths::data_dir(),
+    }
+}
+
+/// Turns a path as stored in library.json into one that can be opened.
+pub fn resolve_media_path(root: &Path, stored: &str) -> String {
+    if Path::new(stored).is_absolute() {
+        stored.to_string()
+    } else {
+        root.join(stored).to_string_lossy().to_string()
+    }
+}
 
-        let ffmpeg_path = Self::find_command("ffmpeg").unwrap_or_else(|| "ffmpeg".to_string());
-        let img2webp_path =
-            Self::find_command("img2webp").unwrap_or_else(|| "img2webp".to_string());
+/// The form a path is stored in: relative to `root` with `/` separators when
+/// it's inside it, absolute otherwise.
+fn relative_media_path(root: &Path, path: &str) -> String {
+    match Path::new(path).strip_prefix(root) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        _ => path.to_string(),
+    }
+}
 
-        println!("Using ffmpeg: {}", ffmpeg_path);
-        println!("Using img2webp: {}", img2webp_path);
+fn default_store() -> Arc<dyn LibraryStore> {
+    Arc::new(FsStore::new(paths::data_dir()))
+}
 
-        let temp_dir = TempDir::new().map_err(|e| e.to_string())?;
-        let frames_dir = temp_dir.path();
+impl Default for CharacterLibrary {
+    fn default() -> Self {
+        Self::empty(default_store())
+    }
+}
 
-        let frames_pattern = frames_dir.join("f%03d.png");
+impl CharacterLibrary {
+    /// An empty library that saves to `store`.
+    pub fn empty(store: Arc<dyn LibraryStore>) -> Self {
+        Self {
+            version: LIBRARY_VERSION,
+            characters: vec![],
+            layouts: vec![],
+            media_root: None,
+            pending_optimization: vec![],
+            load_warning: None,
+            save_error: None,
+            tracker: SaveTracker::default(),
+            store,
+        }
+    }
 
-        println!("Extracting frames with ffmpeg...");
-        let ffmpeg_output = Command::new(&ffmpeg_path)
-            .arg("-y")
-            .arg("-i")
-            .arg(input_path)
-            .arg(frames_pattern.to_str().unwrap())
-            .output()
-            .map_err(|e| e.to_string())?;
+    /// Loads the library from the app's data directory.
+    pub fn load() -> Self {
+        let store = default_store();
+        if !matches!(store.read(LIBRARY_FILE), Ok(None)) {
+            return Self::load_from(store);
+        }
 
-        if !ffmpeg_output.status.success() {
-            return Err(format!(
-                "ffmpeg failed: {}",
-                String::from_utf8_lossy(&ffmpeg_output.stderr)
+        // AnimaTux kept its library under ~/.config; pick it up on first run.
+        if let Some(legacy_dir) = paths::legacy_animatux_dir()
+            && legacy_dir != paths::data_dir()
+            && legacy_dir.join(LIBRARY_FILE).exists()
+        {
+            log_to_file(&format!(
+                "library: importing AnimaTux library from {}",
+                legacy_dir.display()
             ));
+            let mut library = Self::load_from(Arc::new(FsStore::new(legacy_dir)));
+            library.store = store;
+            if library.load_warning.is_none() {
+                library.mark_dirty();
+                library.flush();
+            }
+            return library;
         }
 
-        let mut png_files: Vec<String> = fs::read_dir(frames_dir)
-            .map_err(|e| e.to_string())?
-            .filter_map(|e| e.ok())
-            .map(|e| e.path().to_string_lossy().to_string())
-            .filter(|s| s.ends_with(".png"))
-            .collect();
+        Self::empty(store)
+    }
 
-        png_files.sort();
+    /// Loads and migrates the library kept in `store`. A document that can't be
+    /// read is copied aside first so the next save can't destroy it.
+    pub fn load_from(store: Arc<dyn LibraryStore>) -> Self {
+        let content = match store.read(LIBRARY_FILE) {
+            Ok(Some(content)) => content,
+            Ok(None) => return Self::empty(store),
+            Err(e) => return Self::quarantined(store, &format!("could not read it: {}", e)),
+        };
+        let value: serde_json::Value = match serde_json::from_slice(&content) {
+            Ok(value) => value,
+            Err(e) => return Self::quarantined(store, &format!("invalid JSON: {}", e)),
+        };
 
-        println!("Found {} PNG files", png_files.len());
+        let version = migrations::library_version(&value);
+        let newer = version > LIBRARY_VERSION;
+        let newer_copy = format!("{}.v{}", LIBRARY_FILE, version);
+        if newer {
+            // Loading drops whatever the newer build added, so keep its file around.
+            let _ = store.copy(LIBRARY_FILE, &newer_copy);
+        }
+        let migrated = match migrations::migrate_library(value) {
+            Ok(value) => value,
+            Err(e) => return Self::quarantined(store, &e),
+        };
+        let mut library: Self = match serde_json::from_value(migrated) {
+            Ok(library) => library,
+            Err(e) => return Self::quarantined(store, &e.to_string()),
+        };
 
-        if png_files.is_empty() {
-            return Err("No PNG files created by ffmpeg".to_string());
+        if newer {
+            library.load_warning = Some(format!(
+                "The library was saved by a newer version (schema {}); a copy was kept as {}",
+                version,
+                store.describe(&newer_copy)
+            ));
+        } else if version < LIBRARY_VERSION {
+            log_to_file(&format!(
+                "library: migrated {} from version {} to {}",
+                store.describe(LIBRARY_FILE),
+                version,
+                LIBRARY_VERSION
+            ));
+        }
+        library.version = LIBRARY_VERSION;
+        library.store = store;
+        library.map_paths(resolve_media_path);
+        for character in &mut library.characters {
+            if character.instances.is_empty() {
+                character.instances.push(Instance::default());
+            }
         }
+        library
+    }
 
-        println!("Creating animated WebP with img2webp...");
+    /// Rewrites every character's path with `f(root, path)`.
+    fn map_paths(&mut self, f: fn(&Path, &str) -> String) {
+        let root = media_root_dir(self.media_root.as_deref());
+        for character in &mut self.characters {
+            character.path = f(&root, &character.path);
+        }
+        for entry in &mut self.pending_optimization {
+            entry.original_path = f(&root, &entry.original_path);
+            entry.new_path = f(&root, &entry.new_path);
+        }
+    }
 
-        let mut cmd = Command::new(&img2webp_path);
-        for f in &png_files {
-            cmd.arg(f);
+    fn quarantined(store: Arc<dyn LibraryStore>, reason: &str) -> Self {
+        let warning = store::quarantine(&*store, LIBRARY_FILE, "library", reason);
+        Self {
+            load_warning: Some(warning),
+            ..Self::empty(store)
         }
-        cmd.arg("-o").arg(output_path);
+    }
 
-        let img2webp_output = cmd.output().map_err(|e| e.to_string())?;
+    pub fn save(&self) -> std::io::Result<()> {
+        self.write().map(|_| ())
+    }
 
-        if !img2webp_output.status.success() {
-            return Err(format!(
-                "img2webp failed: {}",
-                String::from_utf8_lossy(&img2webp_output.stderr)
-            ));
-        }
+    /// Paths are absolute in memory and stored relative to the media root where possible.
+    fn write(&self) -> std::io::Result<usize> {
+        let mut stored = self.clone();
+        stored.map_paths(relative_media_path);
+        let content = serde_json::to_string_pretty(&stored).map_err(std::io::Error::other)?;
+        self.store.write(LIBRARY_FILE, content.as_bytes())?;
+        Ok(content.len())
+    }
 
-        println!("Animated WebP saved successfully!");
-        Ok(())
-    }
-
-    fn convert_with_ffmpeg(input_path: &str, webp_path_str: &str) -> Option<String> {
-        let output = Command::new("ffmpeg")
-            .arg("-y")
-            .arg("-i")
-            .arg(input_path)
-            .arg("-c:v")
-            .arg("libwebp")
-            .arg("-lossless")
-            .arg("0")
-            .arg("-loop")
-            .arg("0")
-            .arg("-fps_mode")
-            .arg("vfr")
-            .arg(webp_path_str)
-            .output();
-
-        match output {
-            Ok(o) if o.status.success() => {
-                println!("Conversion successful (ffmpeg)");
-                Some(webp_path_str.to_string())
-            }
-            Ok(o) => {
-                println!("Conversion failed: {}", String::from_utf8_lossy(&o.stderr));
-                None
+    /// Records a change; it's written out by the next `flush_if_due` or `flush`.
+    fn mark_dirty(&mut self) {
+        self.tracker.mark_dirty();
+    }
+
+    /// Writes any pending changes, keeping the error for the UI instead of returning it.
+    pub fn flush(&mut self) {
+        if !self.tracker.is_dirty() {
+            return;
+        }
+        let started = Instant::now();
+        match self.write() {
+            Ok(bytes) => {
+                self.tracker
+                    .record_write("library", bytes, started.elapsed());
+                self.save_error = None;
             }
             Err(e) => {
-                println!("Failed to run ffmpeg: {}", e);
-                None
+                log_to_file(&format!("library: save failed: {}", e));
+                self.tracker.record_failure();
+                self.save_error = Some(format!("Could not save the library: {}", e));
             }
         }
     }
 
-    pub fn add_character(&mut self, path: &str) {
-        let path_lower = path.to_lowercase();
-
-        if !path_lower.ends_with(".apng")
-            && !path_lower.ends_with(".png")
-            && !path_lower.ends_with(".webp")
-            && !path_lower.ends_with(".gif")
-        {
-            return;
+    /// Flushes once changes have settled; call this every frame.
+    pub fn flush_if_due(&mut self) {
+        if self.tracker.is_due() {
+            self.flush();
         }
+    }
 
-        let final_path: String;
-        let name: String;
+    /// How long until pending changes get flushed, `None` when there are none.
+    pub fn flush_due_in(&self) -> Option<Duration> {
+        self.tracker.due_in()
+    }
 
-        if path_lower.ends_with(".apng") {
-            if let Some(converted) = Self::convert_apng_to_webp(path) {
-                final_path = converted;
-            } else {
-                println!("Failed to convert APNG, using original");
-                final_path = path.to_string();
-            }
-        } else {
-            final_path = path.to_string();
+    /// Backups of library.json, newest first.
+    pub fn backups(&self) -> Vec<(usize, SystemTime)> {
+        self.store.backups(LIBRARY_FILE)
+    }
+
+    /// Replaces library.json with backup `n` and loads it.
+    pub fn restore_backup(&self, n: usize) -> std::io::Result<Self> {
+        self.store.restore_backup(LIBRARY_FILE, n)?;
+        Ok(Self::load_from(self.store.clone()))
+    }
+
+    /// Converts an animation to WebP inside the app cache, reusing an
+    /// earlier conversion of the same content when there is one.
+    pub fn convert_to_webp(
+        input_path: &str,
+        options: &ConvertOptions,
+        progress: &Progress,
+    ) -> Result<String, ConvertError> {
+        println!("Converting to WebP: {}", input_path);
+
+        let webp_path = cache::cached_webp(Path::new(input_path), options, progress)?;
+        println!("Conversion successful: {}", webp_path.display());
+        Ok(webp_path.to_string_lossy().to_string())
+    }
+
+    /// Adds a character named after the file. Returns the index of the new entry.
+    pub fn add_character(&mut self, path: &str) -> Result<usize, ImportError> {
+        self.add_character_with_options(path, None, &ImportOptions::default())
+    }
+
+    pub fn add_named_character(&mut self, path: &str, name: &str) -> Result<usize, ImportError> {
+        self.add_character_with_options(path, Some(name), &ImportOptions::default())
+    }
+
+    /// Adds a character, naming it after the file when `name` is `None`.
+    pub fn add_character_with_options(
+        &mut self,
+        path: &str,
+        name: Option<&str>,
+        options: &ImportOptions,
+    ) -> Result<usize, ImportError> {
+        let prepared = Self::prepare_import(path, name, options, &Progress::new())?;
+        self.add_prepared(prepared)
+    }
+
+    /// Converts and probes a file for import, naming it after the file when `name` is `None`.
+    pub fn prepare_import(
+        path: &str,
+        name: Option<&str>,
+        options: &ImportOptions,
+        progress: &Progress,
+    ) -> Result<PreparedImport, ImportError> {
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => PathBuf::from(path)
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("Unknown")
+                .to_string(),
+        };
+
+        if !Path::new(path).is_file() {
+            return Err(ImportError::FileMissing(path.to_string()));
         }
 
-        name = PathBuf::from(&final_path)
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("Unknown")
-            .to_string();
+        progress.set_stage("Reading file");
+        let sniffed = sniff_file(Path::new(path))
+            .map_err(|e| ImportError::DecodeFailed {
+                path: path.to_string(),
+                reason: e.to_string(),
+            })?
+            .ok_or_else(|| ImportError::UnsupportedFormat(path.to_string()))?;
+
+        // Route by the real format: APNGs are converted whatever they're called,
+        // and mislabeled files get a correctly named copy so loaders pick the right decoder.
+        let converted = sniffed.format == MediaFormat::Apng || sniffed.format.is_video();
+        let mut final_path = if sniffed.format == MediaFormat::Apng {
+            Self::convert_to_webp(path, &options.convert, progress)?
+        } else if sniffed.format.is_video() {
+            log_to_file(&format!("library: converting video {}", path));
+            cache::cached_video_webp(Path::new(path), &options.video, &options.convert, progress)?
+                .to_string_lossy()
+                .to_string()
+        } else if !sniffed.format.matches_extension(Path::new(path)) {
+            log_to_file(&format!(
+                "library: {} is actually {}, storing a renamed copy",
+                path,
+                sniffed.format.extension()
+            ));
+            cache::cached_copy(Path::new(path), sniffed.format.extension())
+                .map_err(|e| ImportError::ConversionFailed(e.into()))?
+                .to_string_lossy()
+                .to_string()
+        } else {
+            path.to_string()
+        };
+
+        let probe = |final_path: &str| {
+            media_info::probe(Path::new(final_path)).map_err(|reason| ImportError::DecodeFailed {
+                path: path.to_string(),
+                reason,
+            })
+        };
+        let mut media_info = probe(&final_path)?;
+
+        // Files used as-is are still re-encoded when they break the size limits.
+        let file_size = fs::metadata(&final_path).map(|m| m.len()).unwrap_or(0);
+        if !converted && options.convert.exceeded_by(&media_info, file_size) {
+            log_to_file(&format!(
+                "library: {} exceeds the conversion limits, re-encoding",
+                path
+            ));
+            final_path = Self::convert_to_webp(&final_path, &options.convert, progress)?;
+            media_info = probe(&final_path)?;
+        }
 
-        self.add_named_character(&final_path, &name);
+        let content_hash = cache::content_hash(Path::new(&final_path)).ok();
+        Ok(PreparedImport {
+            name,
+            path: final_path,
+            media_info,
+            content_hash,
+        })
     }
 
-    pub fn add_named_character(&mut self, path: &str, name: &str) {
-        let path_lower = path.to_lowercase();
+    /// The character already using this file, or a file with the same content.
+    fn find_duplicate(&self, path: &str, content_hash: Option<&str>) -> Option<usize> {
+        self.index_by_path(path)
+            .or_else(|| content_hash.and_then(|hash| self.index_by_hash(hash)))
+    }
 
-        if !path_lower.ends_with(".apng")
-            && !path_lower.ends_with(".png")
-            && !path_lower.ends_with(".webp")
-            && !path_lower.ends_with(".gif")
-        {
-            return;
+    /// Adds a prepared import. Returns the index of the new entry.
+    pub fn add_prepared(&mut self, prepared: PreparedImport) -> Result<usize, ImportError> {
+        if let Some(index) = self.find_duplicate(&prepared.path, prepared.content_hash.as_deref()) {
+            return Err(ImportError::Duplicate {
+                index,
+                name: self.characters[index].name.clone(),
+            });
         }
 
-        if !self.characters.iter().any(|c| c.path == path) {
+        self.characters.push(Character {
+            id: Uuid::new_v4(),
+            name: prepared.name,
+            path: prepared.path,
+            instances: vec![Instance::default()],
+            media_info: Some(prepared.media_info),
+            tags: Vec::new(),
+            content_hash: prepared.content_hash,
+        });
+        self.mark_dirty();
+        self.flush();
+        Ok(self.characters.len() - 1)
+    }
+
+    /// Adds the characters from a pack with their speed, scale and tags, in one
+    /// save. Entries whose media is already in the library are skipped. Returns
+    /// the number added.
+    pub fn add_unpacked(&mut self, import: &PackImport) -> usize {
+        let mut added = 0;
+        for unpacked in &import.characters {
+            if self
+                .find_duplicate(&unpacked.path, Some(&unpacked.content_hash))
+                .is_some()
+            {
+                continue;
+            }
             self.characters.push(Character {
-                name: name.to_string(),
-                path: path.to_string(),
-                enabled: false,
-                speed: 0,
-                scale: 1.0,
-                window_pos: None,
-                window_size: None,
+                id: Uuid::new_v4(),
+                name: unpacked.name.clone(),
+                path: unpacked.path.clone(),
+                instances: vec![Instance {
+                    speed: unpacked.speed,
+                    scale: unpacked.scale,
+                    ..Default::default()
+                }],
+                media_info: Some(unpacked.media_info.clone()),
+                tags: unpacked.tags.clone(),
+                content_hash: Some(unpacked.content_hash.clone()),
+            });
+            added += 1;
+        }
+        if added > 0 {
+            self.mark_dirty();
+            self.flush();
+        }
+        added
+    }
+
+    /// Probes and hashes characters that were added before media info and
+    /// content hashes were recorded.
+    pub fn refresh_media_info(&mut self) {
+        let mut changed = false;
+        for character in &mut self.characters {
+            let path = Path::new(&character.path);
+            if character.media_info.is_none()
+                && let Ok(info) = media_info::probe(path)
+            {
+                character.media_info = Some(info);
+                changed = true;
+            }
+            if character.content_hash.is_none()
+                && let Ok(hash) = cache::content_hash(path)
+            {
+                character.content_hash = Some(hash);
+                changed = true;
+            }
+        }
+        if changed {
+            self.mark_dirty();
+        }
+    }
+
+    /// Re-encodes each character into the cache with `options`. Nothing in the
+    /// library changes; the report is applied with `apply_optimization`, and
+    /// entries that wouldn't get smaller are skipped. Runs as a background job.
+    pub fn optimize(
+        characters: &[Character],
+        options: &ConvertOptions,
+        progress: &Progress,
+    ) -> OptimizeReport {
+        let mut report = OptimizeReport::default();
+        for (i, character) in characters.iter().enumerate() {
+            if progress.is_cancelled() {
+                break;
+            }
+            progress.set_stage(format!(
+                "{}/{}: {}",
+                i + 1,
+                characters.len(),
+                character.name
+            ));
+
+            let bytes_before = match fs::metadata(&character.path) {
+                Ok(m) => m.len(),
+                Err(e) => {
+                    report.skipped.push((character.name.clone(), e.to_string()));
+                    continue;
+                }
+            };
+            let new_path = match cache::cached_webp(Path::new(&character.path), options, progress) {
+                Ok(p) => p,
+                Err(e) => {
+                    report.skipped.push((character.name.clone(), e.to_string()));
+                    continue;
+                }
+            };
+            let bytes_after = fs::metadata(&new_path).map(|m| m.len()).unwrap_or(u64::MAX);
+            if bytes_after >= bytes_before {
+                report.skipped.push((
+                    character.name.clone(),
+                    "not smaller after re-encoding".to_string(),
+                ));
+                continue;
+            }
+            let media_info = match media_info::probe(&new_path) {
+                Ok(info) => info,
+                Err(e) => {
+                    report.skipped.push((character.name.clone(), e));
+                    continue;
+                }
+            };
+
+            report.entries.push(OptimizedEntry {
+                id: character.id,
+                name: character.name.clone(),
+                original_path: character.path.clone(),
+                new_path: new_path.to_string_lossy().to_string(),
+                bytes_before,
+                bytes_after,
+                media_info,
+                content_hash: cache::content_hash(&new_path).ok(),
             });
-            self.save();
         }
+        report
+    }
+
+    /// Points every optimized character at its new file in one save. Speed,
+    /// scale and position stay as they are; the original files are left on disk
+    /// until `confirm_optimization` or `revert_optimization`.
+    pub fn apply_optimization(&mut self, report: &OptimizeReport) {
+        self.pending_optimization = report.entries.clone();
+        for entry in &report.entries {
+            if let Some(index) = self.index_by_id(entry.id) {
+                let character = &mut self.characters[index];
+                character.path = entry.new_path.clone();
+                character.media_info = Some(entry.media_info.clone());
+                character.content_hash = entry.content_hash.clone();
+            }
+        }
+        self.mark_dirty();
+        self.flush();
+    }
+
+    /// Keeps the optimized files; the originals are no longer referenced and
+    /// may be removed.
+    pub fn confirm_optimization(&mut self) {
+        self.pending_optimization.clear();
+        self.mark_dirty();
+        self.flush();
+    }
+
+    /// Undoes `apply_optimization`, as long as the originals are still around.
+    pub fn revert_optimization(&mut self, report: &OptimizeReport) {
+        self.pending_optimization.clear();
+        for entry in &report.entries {
+            if !Path::new(&entry.original_path).exists() {
+                continue;
+            }
+            if let Some(index) = self.index_by_id(entry.id) {
+                let character = &mut self.characters[index];
+                character.path = entry.original_path.clone();
+                character.media_info = media_info::probe(Path::new(&entry.original_path)).ok();
+                character.content_hash = cache::content_hash(Path::new(&entry.original_path)).ok();
+            }
+        }
+        self.mark_dirty();
+        self.flush();
     }
 
     pub fn remove_character(&mut self, index: usize) {
         if index < self.characters.len() {
             self.characters.remove(index);
-            self.save();
+            self.mark_dirty();
+            self.flush();
         }
     }
 
+    pub fn index_by_id(&self, id: Uuid) -> Option<usize> {
+        self.characters.iter().position(|c| c.id == id)
+    }
+
     pub fn index_by_path(&self, path: &str) -> Option<usize> {
         self.characters.iter().position(|c| c.path == path)
     }
 
-    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
-        if let Some(character) = self.characters.get_mut(index) {
-            character.enabled = enabled;
-            self.save();
+    pub fn index_by_hash(&self, content_hash: &str) -> Option<usize> {
+        self.characters
+            .iter()
+            .position(|c| c.content_hash.as_deref() == Some(content_hash))
+    }
+
+    /// The character index and the instance index within it.
+    pub fn find_instance(&self, id: Uuid) -> Option<(usize, usize)> {
+        self.characters
+            .iter()
+            .enumerate()
+            .find_map(|(c, character)| {
+                let i = character.instances.iter().position(|i| i.id == id)?;
+                Some((c, i))
+            })
+    }
+
+    pub fn instance(&self, id: Uuid) -> Option<&Instance> {
+        let (c, i) = self.find_instance(id)?;
+        Some(&self.characters[c].instances[i])
+    }
+
+    fn instance_mut(&mut self, id: Uuid) -> Option<&mut Instance> {
+        let (c, i) = self.find_instance(id)?;
+        Some(&mut self.characters[c].instances[i])
+    }
+
+    pub fn set_enabled(&mut self, instance: Uuid, enabled: bool) {
+        if let Some(instance) = self.instance_mut(instance) {
+            instance.enabled = enabled;
+            self.mark_dirty();
         }
     }
 
-    pub fn update_settings(&mut self, index: usize, speed: i64, scale: f32) {
+    /// Shows or hides every instance of a character.
+    pub fn set_character_enabled(&mut self, index: usize, enabled: bool) {
         if let Some(character) = self.characters.get_mut(index) {
-            character.speed = speed;
-            character.scale = scale;
-            self.save();
+            for instance in &mut character.instances {
+                instance.enabled = enabled;
+            }
+            self.mark_dirty();
         }
     }
 
-    pub fn update_position(&mut self, index: usize, pos: [f32; 2]) {
-        if let Some(character) = self.characters.get_mut(index) {
-            character.window_pos = Some(pos);
-            self.save();
+    pub fn update_settings(&mut self, instance: Uuid, speed: Speed, scale: f32) {
+        if let Some(instance) = self.instance_mut(instance) {
+            instance.speed = speed;
+            instance.scale = scale;
+            self.mark_dirty();
         }
     }
 
-    fn get_library_path() -> PathBuf {
-        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-        if cfg!(target_os = "macos") {
-            home.join("Library/Application Support/AnimaMac/library.json")
-        } else if cfg!(windows) {
-            home.join("AppData/Roaming/AnimaMac/library.json")
-        } else {
-            home.join(".config/animatux/library.json")
+    pub fn update_playback(&mut self, instance: Uuid, playback: PlaybackOptions) {
+        if let Some(instance) = self.instance_mut(instance) {
+            instance.playback = playback;
+            self.mark_dirty();
+        }
+    }
+
+    pub fn update_position(&mut self, instance: Uuid, pos: [f32; 2]) {
+        if let Some(instance) = self.instance_mut(instance) {
+            instance.window_pos = Some(pos);
+            self.mark_dirty();
         }
     }
+
+    /// Puts another copy of the character on screen, with the settings of its
+    /// last instance and a little further along. Returns the new instance's id.
+    pub fn add_instance(&mut self, index: usize) -> Option<Uuid> {
+        let character = self.characters.get_mut(index)?;
+        let template = character.instances.last().cloned().unwrap_or_default();
+        let instance = Instance {
+            id: Uuid::new_v4(),
+            enabled: true,
+            window_pos: template
+                .window_pos
+                .map(|[x, y]| [x + INSTANCE_OFFSET, y + INSTANCE_OFFSET]),
+            ..template
+        };
+        let id = instance.id;
+        character.instances.push(instance);
+        self.mark_dirty();
+        self.flush();
+        Some(id)
+    }
+
+    /// Removes one copy. The last instance of a character can't be removed;
+    /// remove the character instead. Returns whether it was removed.
+    pub fn remove_instance(&mut self, id: Uuid) -> bool {
+        let Some((c, i)) = self.find_instance(id) else {
+            return false;
+        };
+        if self.characters[c].instances.len() < 2 {
+            return false;
+        }
+        self.characters[c].instances.remove(i);
+        self.mark_dirty();
+        self.flush();
+        true
+    }
+
+    /// Indices of characters whose media file isn't there.
+    pub fn missing_characters(&self) -> Vec<usize> {
+        self.characters
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !Path::new(&c.path).is_file())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Points a character at `new_path`, re-probing and re-hashing the file,
+    /// which may not have the content the character had. Returns false if
+    /// there's no such character.
+    pub fn relink(&mut self, id: Uuid, new_path: &str) -> bool {
+        let Some(index) = self.index_by_id(id) else {
+            return false;
+        };
+        let character = &mut self.characters[index];
+        character.path = new_path.to_string();
+        character.media_info = media_info::probe(Path::new(new_path)).ok();
+        character.content_hash = cache::content_hash(Path::new(new_path)).ok();
+        self.mark_dirty();
+        true
+    }
+
+    /// Changes the directory paths are stored relative to. Missing files
+    /// that exist at the same place under the new root are relinked there,
+    /// which fixes a library synced from a machine with another folder layout.
+    /// Returns the number relinked.
+    pub fn set_media_root(&mut self, media_root: Option<String>) -> usize {
+        let old_root = media_root_dir(self.media_root.as_deref());
+        self.media_root = media_root.filter(|r| !r.trim().is_empty());
+        let new_root = media_root_dir(self.media_root.as_deref());
+
+        let moves: Vec<(Uuid, String)> = self
+            .characters
+            .iter()
+            .filter(|c| !Path::new(&c.path).is_file())
+            .filter_map(|c| {
+                let relative = Path::new(&c.path).strip_prefix(&old_root).ok()?;
+                let candidate = new_root.join(relative);
+                candidate
+                    .is_file()
+                    .then(|| (c.id, candidate.to_string_lossy().to_string()))
+            })
+            .collect();
+        for (id, new_path) in &moves {
+            self.relink(*id, new_path);
+        }
+        self.mark_dirty();
+        self.flush();
+        moves.len()
+    }
+
+    /// Saves the enabled instances as layout `name`, replacing a layout of
+    /// the same name but keeping its shortcut. Returns the layout's index.
+    pub fn save_layout(&mut self, name: &str) -> usize {
+        let mut layout = SceneLayout::capture(name, &self.characters);
+        let index = match self.layouts.iter().position(|l| l.name == name) {
+            Some(index) => {
+                layout.shortcut = self.layouts[index].shortcut;
+                self.layouts[index] = layout;
+                index
+            }
+            None => {
+                self.layouts.push(layout);
+                self.layouts.len() - 1
+            }
+        };
+        self.mark_dirty();
+        index
+    }
+
+    /// Enables exactly the instances in the layout and moves them into place.
+    /// Returns the instances whose windows need to move; entries for
+    /// instances no longer in the library are skipped.
+    pub fn apply_layout(&mut self, index: usize) -> Vec<Uuid> {
+        let Some(layout) = self.layouts.get(index) else {
+            return Vec::new();
+        };
+        let mut moved = Vec::new();
+        let instances = self.characters.iter_mut().flat_map(|c| &mut c.instances);
+        for instance in instances {
+            match layout.entry(instance.id) {
+                Some(entry) => {
+                    instance.enabled = true;
+                    instance.scale = entry.scale;
+                    instance.speed = entry.speed;
+                    if entry.window_pos.is_some() {
+                        instance.window_pos = entry.window_pos;
+                        moved.push(instance.id);
+                    }
+                }
+                None => instance.enabled = false,
+            }
+        }
+        self.mark_dirty();
+        moved
+    }
+
+    pub fn remove_layout(&mut self, index: usize) {
+        if index < self.layouts.len() {
+            self.layouts.remove(index);
+            self.mark_dirty();
+        }
+    }
+
+    /// Binds the layout to a shortcut slot, taking the slot from any other layout.
+    pub fn set_layout_shortcut(&mut self, index: usize, shortcut: Option<u8>) {
+        if index >= self.layouts.len() {
+            return;
+        }
+        if shortcut.is_some() {
+            for layout in &mut self.layouts {
+                if layout.shortcut == shortcut {
+                    layout.shortcut = None;
+                }
+            }
+        }
+        self.layouts[index].shortcut = shortcut;
+        self.mark_dirty();
+    }
+
+    pub fn layout_for_shortcut(&self, slot: u8) -> Option<usize> {
+        self.layouts.iter().position(|l| l.shortcut == Some(slot))
+    }
 }
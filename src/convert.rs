@@ -0,0 +1,890 @@
+// Media conversion to animated WebP with pluggable backends
+use crate::apng::{self, Animation};
+use crate::jobs::{self, Progress};
+use crate::logging::log_to_file;
+use crate::media_info::{self, MediaInfo};
+use crate::paths;
+use crate::playback;
+use crate::sniff::{MediaFormat, sniff_file};
+use image::codecs::gif::GifDecoder;
+use image::{AnimationDecoder, ImageDecoder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+use tempfile::TempDir;
+
+#[derive(Debug)]
+pub enum ConvertError {
+    Io(std::io::Error),
+    Decode(String),
+    Encode(String),
+    Unsupported(String),
+    ToolMissing(&'static str),
+    ToolFailed { tool: &'static str, stderr: String },
+    AllBackendsFailed(Vec<(&'static str, ConvertError)>),
+    Cancelled,
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Io(e) => write!(f, "I/O error: {}", e),
+            ConvertError::Decode(e) => write!(f, "decode failed: {}", e),
+            ConvertError::Encode(e) => write!(f, "encode failed: {}", e),
+            ConvertError::Unsupported(what) => write!(f, "unsupported input: {}", what),
+            ConvertError::ToolMissing(tool) => write!(f, "{} not found", tool),
+            ConvertError::ToolFailed { tool, stderr } => write!(f, "{} failed: {}", tool, stderr),
+            ConvertError::AllBackendsFailed(errors) => {
+                if errors.is_empty() {
+                    return write!(f, "no conversion backend available (is ffmpeg installed?)");
+                }
+                write!(f, "all conversion backends failed")?;
+                for (name, e) in errors {
+                    write!(f, "; {}: {}", name, e)?;
+                }
+                Ok(())
+            }
+            ConvertError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+impl From<std::io::Error> for ConvertError {
+    fn from(e: std::io::Error) -> Self {
+        ConvertError::Io(e)
+    }
+}
+
+/// Encoding settings shared by every backend.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConvertOptions {
+    pub lossless: bool,
+    /// Lossy quality, 0-100.
+    pub quality: f32,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// Frames shown for less than `1000 / max_fps` ms are dropped.
+    pub max_fps: Option<f32>,
+    /// Output larger than this is re-encoded at lower quality, then smaller sizes.
+    pub max_output_bytes: Option<u64>,
+    /// Extra downscale factor on top of the size limits, used when shrinking to `max_output_bytes`.
+    pub scale: f32,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            lossless: false,
+            quality: 75.0,
+            max_width: None,
+            max_height: None,
+            max_fps: None,
+            max_output_bytes: None,
+            scale: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityPreset {
+    Small,
+    Balanced,
+    High,
+    Lossless,
+}
+
+impl QualityPreset {
+    pub const ALL: [QualityPreset; 4] = [
+        QualityPreset::Small,
+        QualityPreset::Balanced,
+        QualityPreset::High,
+        QualityPreset::Lossless,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            QualityPreset::Small => "Small",
+            QualityPreset::Balanced => "Balanced",
+            QualityPreset::High => "High",
+            QualityPreset::Lossless => "Lossless",
+        }
+    }
+
+    pub fn options(&self) -> ConvertOptions {
+        match self {
+            QualityPreset::Small => ConvertOptions {
+                quality: 60.0,
+                max_width: Some(512),
+                max_height: Some(512),
+                max_fps: Some(15.0),
+                max_output_bytes: Some(2 * 1024 * 1024),
+                ..Default::default()
+            },
+            QualityPreset::Balanced => ConvertOptions {
+                quality: 80.0,
+                max_width: Some(1024),
+                max_height: Some(1024),
+                max_fps: Some(30.0),
+                ..Default::default()
+            },
+            QualityPreset::High => ConvertOptions {
+                quality: 92.0,
+                ..Default::default()
+            },
+            QualityPreset::Lossless => ConvertOptions {
+                lossless: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl ConvertOptions {
+    /// Whether a file with this info and size breaks any of the limits and should be re-encoded.
+    pub fn exceeded_by(&self, info: &MediaInfo, file_size: u64) -> bool {
+        let min_delay = self.max_fps.filter(|f| *f > 0.0).map(|f| 1000.0 / f);
+        self.max_width.is_some_and(|w| info.width > w)
+            || self.max_height.is_some_and(|h| info.height > h)
+            || self.max_output_bytes.is_some_and(|max| file_size > max)
+            || min_delay.is_some_and(|min| info.frame_delays_ms.iter().any(|&d| (d as f32) < min))
+    }
+
+    /// Next, smaller setting to try when the output is over `max_output_bytes`:
+    /// lower quality first, then shrink. `None` once nothing is left to give.
+    pub fn reduced(&self) -> Option<ConvertOptions> {
+        let mut next = self.clone();
+        if self.lossless {
+            next.lossless = false;
+            next.quality = 80.0;
+        } else if self.quality > 30.0 {
+            next.quality = (self.quality - 15.0).max(30.0);
+        } else if self.scale > 0.3 {
+            next.scale = self.scale * 0.75;
+        } else {
+            return None;
+        }
+        Some(next)
+    }
+
+    /// Distinguishes cached conversions of the same file made with different options.
+    pub fn cache_key(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_string(self).unwrap_or_default());
+        format!("{:x}", hasher.finalize())[..12].to_string()
+    }
+
+    fn ffmpeg_scale_filter(&self) -> Option<String> {
+        if self.max_width.is_none() && self.max_height.is_none() && self.scale >= 1.0 {
+            return None;
+        }
+        let limit = |max: Option<u32>, dim: &str| match max {
+            Some(max) => format!("min({},{}*{})", max, dim, self.scale),
+            None => format!("{}*{}", dim, self.scale),
+        };
+        Some(format!(
+            "scale=w='{}':h='{}':force_original_aspect_ratio=decrease",
+            limit(self.max_width, "iw"),
+            limit(self.max_height, "ih")
+        ))
+    }
+
+    fn ffmpeg_quality_args(&self) -> Vec<String> {
+        if self.lossless {
+            vec!["-lossless".into(), "1".into()]
+        } else {
+            vec![
+                "-lossless".into(),
+                "0".into(),
+                "-quality".into(),
+                self.quality.to_string(),
+            ]
+        }
+    }
+}
+
+pub trait MediaConverter {
+    fn name(&self) -> &'static str;
+
+    /// Whether the backend can run at all on this machine (e.g. its tools are installed).
+    fn is_available(&self) -> bool;
+
+    fn supports(&self, format: MediaFormat) -> bool;
+
+    fn convert(
+        &self,
+        input: &Path,
+        format: MediaFormat,
+        output: &Path,
+        options: &ConvertOptions,
+        progress: &Progress,
+    ) -> Result<(), ConvertError>;
+}
+
+/// Looks for an external tool in the usual Homebrew/system locations, then `PATH`.
+pub fn find_command(name: &str) -> Option<String> {
+    let paths = [
+        format!("/opt/homebrew/bin/{}", name),
+        format!("/usr/local/bin/{}", name),
+        format!("/usr/bin/{}", name),
+    ];
+
+    for path in &paths {
+        if Path::new(path).exists() {
+            return Some(path.clone());
+        }
+    }
+
+    if let Ok(output) = Command::new("which").arg(name).output()
+        && output.status.success()
+    {
+        let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !path.is_empty() {
+            return Some(path);
+        }
+    }
+
+    None
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| extensions.iter().any(|x| e.eq_ignore_ascii_case(x)))
+        .unwrap_or(false)
+}
+
+/// Forces the demuxer for APNGs, which ffmpeg would otherwise read as a still
+/// image when they carry a .png extension.
+fn ffmpeg_input_format(format: MediaFormat) -> &'static [&'static str] {
+    match format {
+        MediaFormat::Apng => &["-f", "apng"],
+        _ => &[],
+    }
+}
+
+/// `ffmpeg` with progress reports on stdout, which `run_tool` turns into frame counts.
+fn ffmpeg_command(ffmpeg: &str) -> Command {
+    let mut cmd = Command::new(ffmpeg);
+    cmd.arg("-y").arg("-nostats").arg("-progress").arg("pipe:1");
+    cmd
+}
+
+fn run_tool(
+    tool: &'static str,
+    cmd: &mut Command,
+    progress: &Progress,
+) -> Result<(), ConvertError> {
+    let frames = progress.clone();
+    let output = jobs::run_command(cmd, progress, move |line| {
+        if let Some(frame) = line.strip_prefix("frame=")
+            && let Ok(frame) = frame.trim().parse()
+        {
+            frames.set_done(frame);
+        }
+    })?
+    .ok_or(ConvertError::Cancelled)?;
+    if !output.status.success() {
+        return Err(ConvertError::ToolFailed {
+            tool,
+            stderr: output.stderr.trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Pure Rust pipeline: built-in APNG decoder (or the `image`/`webp-animation`
+/// decoders for GIF and WebP) feeding libwebp through `webp-animation`.
+pub struct NativeConverter;
+
+impl MediaConverter for NativeConverter {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn supports(&self, format: MediaFormat) -> bool {
+        matches!(
+            format,
+            MediaFormat::Apng | MediaFormat::Png | MediaFormat::Gif | MediaFormat::Webp
+        )
+    }
+
+    fn convert(
+        &self,
+        input: &Path,
+        format: MediaFormat,
+        output: &Path,
+        options: &ConvertOptions,
+        progress: &Progress,
+    ) -> Result<(), ConvertError> {
+        progress.set_stage("Decoding frames");
+        let mut animation = match format {
+            MediaFormat::Gif => decode_gif(input)?,
+            MediaFormat::Webp => decode_webp(input)?,
+            _ => apng::decode_apng(input).map_err(ConvertError::Decode)?,
+        };
+        if progress.is_cancelled() {
+            return Err(ConvertError::Cancelled);
+        }
+        animation.downscale(options.max_width, options.max_height, options.scale);
+        if let Some(max_fps) = options.max_fps {
+            animation.limit_fps(max_fps);
+        }
+
+        progress.set_stage("Encoding frames");
+        progress.set_total(animation.frames.len() as u64);
+        apng::encode_webp(
+            &animation,
+            output,
+            options.lossless,
+            options.quality,
+            progress,
+        )
+        .map_err(|e| {
+            if progress.is_cancelled() {
+                ConvertError::Cancelled
+            } else {
+                ConvertError::Encode(e)
+            }
+        })
+    }
+}
+
+fn decode_gif(input: &Path) -> Result<Animation, ConvertError> {
+    let decoder = GifDecoder::new(BufReader::new(File::open(input)?))
+        .map_err(|e| ConvertError::Decode(e.to_string()))?;
+    let (width, height) = decoder.dimensions();
+
+    let mut frames = vec![];
+    for frame in decoder.into_frames() {
+        let frame = frame.map_err(|e| ConvertError::Decode(e.to_string()))?;
+        let delay = Duration::from(frame.delay());
+        frames.push(apng::Frame {
+            delay_ms: playback::shown_delay(delay).as_millis() as u32,
+            rgba: frame.into_buffer().into_raw(),
+        });
+    }
+
+    Ok(Animation {
+        width,
+        height,
+        loop_count: media_info::gif_loop_count(&fs::read(input)?),
+        frames,
+    })
+}
+
+fn decode_webp(input: &Path) -> Result<Animation, ConvertError> {
+    let bytes = fs::read(input)?;
+    let decoder =
+        webp_animation::Decoder::new(&bytes).map_err(|e| ConvertError::Decode(e.to_string()))?;
+    let (width, height) = decoder.dimensions();
+
+    // Decoder timestamps mark the end of each frame.
+    let mut frames = vec![];
+    let mut last = 0;
+    for frame in decoder.into_iter() {
+        let delay = Duration::from_millis((frame.timestamp() - last) as u64);
+        frames.push(apng::Frame {
+            rgba: frame.data().to_vec(),
+            delay_ms: playback::shown_delay(delay).as_millis() as u32,
+        });
+        last = frame.timestamp();
+    }
+
+    Ok(Animation {
+        width,
+        height,
+        loop_count: media_info::webp_chunk_info(&bytes).loop_count,
+        frames,
+    })
+}
+
+/// Extracts frames with `ffmpeg` and assembles them with `img2webp`.
+pub struct FfmpegImg2webpConverter {
+    ffmpeg: Option<String>,
+    img2webp: Option<String>,
+}
+
+impl FfmpegImg2webpConverter {
+    pub fn new() -> Self {
+        Self {
+            ffmpeg: find_command("ffmpeg"),
+            img2webp: find_command("img2webp"),
+        }
+    }
+}
+
+impl Default for FfmpegImg2webpConverter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MediaConverter for FfmpegImg2webpConverter {
+    fn name(&self) -> &'static str {
+        "ffmpeg+img2webp"
+    }
+
+    fn is_available(&self) -> bool {
+        self.ffmpeg.is_some() && self.img2webp.is_some()
+    }
+
+    fn supports(&self, format: MediaFormat) -> bool {
+        matches!(
+            format,
+            MediaFormat::Apng | MediaFormat::Png | MediaFormat::Gif
+        )
+    }
+
+    fn convert(
+        &self,
+        input: &Path,
+        format: MediaFormat,
+        output: &Path,
+        options: &ConvertOptions,
+        progress: &Progress,
+    ) -> Result<(), ConvertError> {
+        let ffmpeg = self
+            .ffmpeg
+            .as_ref()
+            .ok_or(ConvertError::ToolMissing("ffmpeg"))?;
+        let img2webp = self
+            .img2webp
+            .as_ref()
+            .ok_or(ConvertError::ToolMissing("img2webp"))?;
+
+        // img2webp only sees PNGs, so timing and looping come from the source.
+        let info = media_info::probe(input).map_err(ConvertError::Decode)?;
+
+        let scratch = paths::cache_dir();
+        fs::create_dir_all(&scratch)?;
+        let temp_dir = TempDir::new_in(scratch)?;
+        let frames_dir = temp_dir.path();
+
+        progress.set_stage("Extracting frames");
+        let mut extract = ffmpeg_command(ffmpeg);
+        extract
+            .args(ffmpeg_input_format(format))
+            .arg("-i")
+            .arg(input);
+        let filters: Vec<String> = options
+            .max_fps
+            .map(|fps| format!("fps={}", fps))
+            .into_iter()
+            .chain(options.ffmpeg_scale_filter())
+            .collect();
+        if !filters.is_empty() {
+            extract.arg("-vf").arg(filters.join(","));
+        }
+        if options.max_fps.is_none() {
+            // One PNG per source frame, so each keeps its own delay.
+            extract.arg("-fps_mode").arg("passthrough");
+        }
+        run_tool(
+            "ffmpeg",
+            extract.arg(frames_dir.join("f%03d.png")),
+            progress,
+        )?;
+
+        let mut png_files: Vec<_> = fs::read_dir(frames_dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| has_extension(p, &["png"]))
+            .collect();
+        png_files.sort();
+
+        if png_files.is_empty() {
+            return Err(ConvertError::Decode(
+                "no PNG frames created by ffmpeg".to_string(),
+            ));
+        }
+
+        // The fps filter evens the frames out; otherwise they're the source's own.
+        let delays: Vec<u32> = match options.max_fps {
+            Some(fps) => vec![(1000.0 / fps) as u32; png_files.len()],
+            // A still image has no delay to keep.
+            None if png_files.len() == 1 => vec![],
+            None if info.frame_delays_ms.len() == png_files.len() => info
+                .frame_delays_ms
+                .iter()
+                .map(|&ms| {
+                    playback::shown_delay(Duration::from_millis(ms as u64)).as_millis() as u32
+                })
+                .collect(),
+            None => {
+                return Err(ConvertError::Decode(format!(
+                    "ffmpeg extracted {} frames, expected {}",
+                    png_files.len(),
+                    info.frame_delays_ms.len()
+                )));
+            }
+        };
+
+        progress.set_stage("Encoding frames");
+        let mut cmd = Command::new(img2webp);
+        cmd.arg("-loop").arg(info.loop_count.to_string());
+        if options.lossless {
+            cmd.arg("-lossless");
+        } else {
+            cmd.arg("-lossy").arg("-q").arg(options.quality.to_string());
+        }
+        // `-d` applies to the frames after it.
+        for (i, png) in png_files.iter().enumerate() {
+            if let Some(delay) = delays.get(i) {
+                cmd.arg("-d").arg(delay.to_string());
+            }
+            cmd.arg(png);
+        }
+        cmd.arg("-o").arg(output);
+        run_tool("img2webp", &mut cmd, progress)
+    }
+}
+
+/// Single `ffmpeg` run using its `libwebp` encoder.
+pub struct FfmpegLibwebpConverter {
+    ffmpeg: Option<String>,
+}
+
+impl FfmpegLibwebpConverter {
+    pub fn new() -> Self {
+        Self {
+            ffmpeg: find_command("ffmpeg"),
+        }
+    }
+}
+
+impl Default for FfmpegLibwebpConverter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MediaConverter for FfmpegLibwebpConverter {
+    fn name(&self) -> &'static str {
+        "ffmpeg-libwebp"
+    }
+
+    fn is_available(&self) -> bool {
+        self.ffmpeg.is_some()
+    }
+
+    fn supports(&self, format: MediaFormat) -> bool {
+        matches!(
+            format,
+            MediaFormat::Apng | MediaFormat::Png | MediaFormat::Gif
+        )
+    }
+
+    fn convert(
+        &self,
+        input: &Path,
+        format: MediaFormat,
+        output: &Path,
+        options: &ConvertOptions,
+        progress: &Progress,
+    ) -> Result<(), ConvertError> {
+        let ffmpeg = self
+            .ffmpeg
+            .as_ref()
+            .ok_or(ConvertError::ToolMissing("ffmpeg"))?;
+        progress.set_stage("Converting frames");
+        let mut cmd = ffmpeg_command(ffmpeg);
+        cmd.args(ffmpeg_input_format(format)).arg("-i").arg(input);
+        if let Some(filter) = options.ffmpeg_scale_filter() {
+            cmd.arg("-vf").arg(filter);
+        }
+        if let Some(fps) = options.max_fps {
+            cmd.arg("-fpsmax").arg(fps.to_string());
+        }
+        cmd.arg("-c:v")
+            .arg("libwebp")
+            .args(options.ffmpeg_quality_args())
+            .arg("-loop")
+            .arg("0")
+            .arg("-fps_mode")
+            .arg("vfr")
+            .arg(output);
+        run_tool("ffmpeg", &mut cmd, progress)
+    }
+}
+
+/// Trimming and frame rate settings for video imports.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VideoOptions {
+    /// Seconds to skip at the start of the clip.
+    pub start_secs: f32,
+    /// Timestamp to stop at, `None` for the end of the clip.
+    pub end_secs: Option<f32>,
+    pub max_duration_secs: Option<f32>,
+    /// Output frame rate, `None` keeps the source rate.
+    pub fps: Option<f32>,
+}
+
+impl VideoOptions {
+    /// Length of the converted clip, taking both the end point and the maximum into account.
+    pub fn duration_secs(&self) -> Option<f32> {
+        let until_end = self.end_secs.map(|end| (end - self.start_secs).max(0.0));
+        match (until_end, self.max_duration_secs) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Distinguishes cached conversions of the same file made with different options.
+    pub fn cache_key(&self) -> String {
+        format!(
+            "s{}-d{}-f{}",
+            self.start_secs,
+            self.duration_secs()
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+            self.fps.map(|f| f.to_string()).unwrap_or_default()
+        )
+    }
+}
+
+/// Converts video clips with `ffmpeg`, keeping the alpha channel of VP8/VP9 WebMs
+/// and alpha-capable QuickTime codecs.
+pub struct FfmpegVideoConverter {
+    ffmpeg: Option<String>,
+    ffprobe: Option<String>,
+    options: VideoOptions,
+}
+
+impl FfmpegVideoConverter {
+    pub fn new(options: VideoOptions) -> Self {
+        Self {
+            ffmpeg: find_command("ffmpeg"),
+            ffprobe: find_command("ffprobe"),
+            options,
+        }
+    }
+
+    fn video_codec(&self, input: &Path) -> Option<String> {
+        let output = Command::new(self.ffprobe.as_ref()?)
+            .arg("-v")
+            .arg("error")
+            .arg("-select_streams")
+            .arg("v:0")
+            .arg("-show_entries")
+            .arg("stream=codec_name")
+            .arg("-of")
+            .arg("default=nokey=1:noprint_wrappers=1")
+            .arg(input)
+            .output()
+            .ok()?;
+        let codec = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !codec.is_empty()).then_some(codec)
+    }
+}
+
+impl Default for FfmpegVideoConverter {
+    fn default() -> Self {
+        Self::new(VideoOptions::default())
+    }
+}
+
+impl MediaConverter for FfmpegVideoConverter {
+    fn name(&self) -> &'static str {
+        "ffmpeg-video"
+    }
+
+    fn is_available(&self) -> bool {
+        self.ffmpeg.is_some()
+    }
+
+    fn supports(&self, format: MediaFormat) -> bool {
+        format.is_video()
+    }
+
+    fn convert(
+        &self,
+        input: &Path,
+        format: MediaFormat,
+        output: &Path,
+        options: &ConvertOptions,
+        progress: &Progress,
+    ) -> Result<(), ConvertError> {
+        let ffmpeg = self
+            .ffmpeg
+            .as_ref()
+            .ok_or(ConvertError::ToolMissing("ffmpeg"))?;
+
+        progress.set_stage("Converting video");
+        if let (Some(fps), Some(duration)) = (self.options.fps, self.options.duration_secs()) {
+            progress.set_total((fps * duration).ceil() as u64);
+        }
+
+        let mut cmd = ffmpeg_command(ffmpeg);
+        // ffmpeg's built-in VP8/VP9 decoders drop the alpha channel, libvpx keeps it.
+        match self.video_codec(input).as_deref() {
+            Some("vp9") => {
+                cmd.arg("-c:v").arg("libvpx-vp9");
+            }
+            Some("vp8") => {
+                cmd.arg("-c:v").arg("libvpx");
+            }
+            _ => {}
+        }
+        if self.options.start_secs > 0.0 {
+            cmd.arg("-ss").arg(self.options.start_secs.to_string());
+        }
+        cmd.arg("-i").arg(input);
+        if let Some(duration) = self.options.duration_secs() {
+            cmd.arg("-t").arg(duration.to_string());
+        }
+        let filters: Vec<String> = self
+            .options
+            .fps
+            .map(|fps| format!("fps={}", fps))
+            .into_iter()
+            .chain(options.ffmpeg_scale_filter())
+            .collect();
+        if !filters.is_empty() {
+            cmd.arg("-vf").arg(filters.join(","));
+        }
+        if self.options.fps.is_none()
+            && let Some(fps) = options.max_fps
+        {
+            cmd.arg("-fpsmax").arg(fps.to_string());
+        }
+        // H.264 MP4s can't carry alpha, so don't pay for an empty alpha plane.
+        let pix_fmt = if format == MediaFormat::Mp4 {
+            "yuv420p"
+        } else {
+            "yuva420p"
+        };
+        cmd.arg("-an")
+            .arg("-c:v")
+            .arg("libwebp")
+            .args(options.ffmpeg_quality_args())
+            .arg("-pix_fmt")
+            .arg(pix_fmt)
+            .arg("-loop")
+            .arg("0")
+            .arg(output);
+
+        run_tool("ffmpeg", &mut cmd, progress)
+    }
+}
+
+/// All backends in the order they should be tried.
+pub fn backends() -> Vec<Box<dyn MediaConverter>> {
+    vec![
+        Box::new(NativeConverter),
+        Box::new(FfmpegImg2webpConverter::new()),
+        Box::new(FfmpegLibwebpConverter::new()),
+        Box::new(FfmpegVideoConverter::default()),
+    ]
+}
+
+/// Converts `input` to an animated WebP at `output`, trying each available backend
+/// in turn. Returns the name of the backend that succeeded.
+pub fn convert_to_webp(
+    input: &Path,
+    output: &Path,
+    options: &ConvertOptions,
+    progress: &Progress,
+) -> Result<&'static str, ConvertError> {
+    run_backends(input, output, options, progress, backends())
+}
+
+/// Converts a video clip to an animated WebP, trimmed and resampled per `video`.
+pub fn convert_video_to_webp(
+    input: &Path,
+    output: &Path,
+    video: &VideoOptions,
+    options: &ConvertOptions,
+    progress: &Progress,
+) -> Result<&'static str, ConvertError> {
+    run_backends(
+        input,
+        output,
+        options,
+        progress,
+        vec![Box::new(FfmpegVideoConverter::new(video.clone()))],
+    )
+}
+
+fn run_backends(
+    input: &Path,
+    output: &Path,
+    options: &ConvertOptions,
+    progress: &Progress,
+    backends: Vec<Box<dyn MediaConverter>>,
+) -> Result<&'static str, ConvertError> {
+    let format = sniff_file(input)?
+        .ok_or_else(|| ConvertError::Unsupported(input.display().to_string()))?
+        .format;
+
+    let mut errors = vec![];
+    for backend in backends {
+        if !backend.is_available() || !backend.supports(format) {
+            continue;
+        }
+        log_to_file(&format!(
+            "convert: {} -> {} using {}",
+            input.display(),
+            output.display(),
+            backend.name()
+        ));
+        match convert_within_limits(backend.as_ref(), input, format, output, options, progress) {
+            Ok(()) => return Ok(backend.name()),
+            Err(ConvertError::Cancelled) => {
+                log_to_file(&format!("convert: {} cancelled", input.display()));
+                let _ = fs::remove_file(output);
+                return Err(ConvertError::Cancelled);
+            }
+            Err(e) => {
+                log_to_file(&format!("convert: {} failed: {}", backend.name(), e));
+                let _ = fs::remove_file(output);
+                errors.push((backend.name(), e));
+            }
+        }
+    }
+    Err(ConvertError::AllBackendsFailed(errors))
+}
+
+/// Runs one backend, stepping the options down until the output fits `max_output_bytes`.
+fn convert_within_limits(
+    backend: &dyn MediaConverter,
+    input: &Path,
+    format: MediaFormat,
+    output: &Path,
+    options: &ConvertOptions,
+    progress: &Progress,
+) -> Result<(), ConvertError> {
+    let mut options = options.clone();
+    loop {
+        backend.convert(input, format, output, &options, progress)?;
+        let Some(max) = options.max_output_bytes else {
+            return Ok(());
+        };
+        let size = fs::metadata(output)?.len();
+        if size <= max {
+            return Ok(());
+        }
+        match options.reduced() {
+            Some(next) => {
+                log_to_file(&format!(
+                    "convert: output is {} bytes, over the {} byte limit; retrying with quality {} scale {:.2}",
+                    size, max, next.quality, next.scale
+                ));
+                options = next;
+            }
+            // Best effort: keep the smallest result rather than failing the import.
+            None => return Ok(()),
+        }
+    }
+}
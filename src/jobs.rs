@@ -0,0 +1,230 @@
+// Background jobs: downloads and conversions run on worker threads, report
+// progress through a shared handle and send their results back over a channel
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often a running external command is checked for cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Progress and cancellation state shared between a job and the UI. Cloning
+/// gives another handle to the same state.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    inner: Arc<ProgressInner>,
+}
+
+#[derive(Debug, Default)]
+struct ProgressInner {
+    cancelled: AtomicBool,
+    stage: Mutex<String>,
+    done: AtomicU64,
+    /// 0 while the total is unknown.
+    total: AtomicU64,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a new stage, resetting the step counts.
+    pub fn set_stage(&self, stage: impl Into<String>) {
+        if let Ok(mut current) = self.inner.stage.lock() {
+            *current = stage.into();
+        }
+        self.inner.done.store(0, Ordering::Relaxed);
+        self.inner.total.store(0, Ordering::Relaxed);
+    }
+
+    pub fn set_total(&self, total: u64) {
+        self.inner.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn set_done(&self, done: u64) {
+        self.inner.done.store(done, Ordering::Relaxed);
+    }
+
+    pub fn stage(&self) -> String {
+        self.inner
+            .stage
+            .lock()
+            .map(|s| s.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn done(&self) -> u64 {
+        self.inner.done.load(Ordering::Relaxed)
+    }
+
+    /// Fraction of the current stage that is complete, `None` when the total isn't known.
+    pub fn fraction(&self) -> Option<f32> {
+        match self.inner.total.load(Ordering::Relaxed) {
+            0 => None,
+            total => Some((self.done() as f32 / total as f32).min(1.0)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+pub type JobId = u64;
+
+pub struct JobInfo {
+    pub id: JobId,
+    pub label: String,
+    pub progress: Progress,
+    handle: JoinHandle<()>,
+}
+
+/// Runs jobs on their own threads; finished results are collected with `poll`.
+pub struct JobQueue<T> {
+    next_id: JobId,
+    jobs: Vec<JobInfo>,
+    sender: Sender<(JobId, T)>,
+    receiver: Receiver<(JobId, T)>,
+}
+
+impl<T: Send + 'static> Default for JobQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send + 'static> JobQueue<T> {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            next_id: 0,
+            jobs: vec![],
+            sender,
+            receiver,
+        }
+    }
+
+    pub fn spawn(
+        &mut self,
+        label: impl Into<String>,
+        work: impl FnOnce(&Progress) -> T + Send + 'static,
+    ) -> JobId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let progress = Progress::new();
+        let sender = self.sender.clone();
+        let job_progress = progress.clone();
+        let handle = thread::spawn(move || {
+            let result = work(&job_progress);
+            // The receiver only goes away when the app shuts down.
+            let _ = sender.send((id, result));
+        });
+
+        self.jobs.push(JobInfo {
+            id,
+            label: label.into(),
+            progress,
+            handle,
+        });
+        id
+    }
+
+    /// Jobs that are still running.
+    pub fn jobs(&self) -> &[JobInfo] {
+        &self.jobs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    pub fn cancel(&self, id: JobId) {
+        if let Some(job) = self.jobs.iter().find(|j| j.id == id) {
+            job.progress.cancel();
+        }
+    }
+
+    pub fn cancel_all(&self) {
+        for job in &self.jobs {
+            job.progress.cancel();
+        }
+    }
+
+    /// Returns the results of jobs that finished since the last call and drops
+    /// them from the job list.
+    pub fn poll(&mut self) -> Vec<(JobId, T)> {
+        let results: Vec<(JobId, T)> = self.receiver.try_iter().collect();
+        // A job whose thread is done without sending a result has panicked;
+        // either way there's nothing left to show for it.
+        self.jobs.retain(|job| {
+            !job.handle.is_finished() && !results.iter().any(|(id, _)| *id == job.id)
+        });
+        results
+    }
+}
+
+pub struct CommandOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs `cmd` to completion, passing each line of its stdout to `on_line` as it
+/// arrives. Kills the process and returns `None` if the job is cancelled.
+pub fn run_command(
+    cmd: &mut Command,
+    progress: &Progress,
+    mut on_line: impl FnMut(&str) + Send + 'static,
+) -> io::Result<Option<CommandOutput>> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take();
+    let stdout_reader = thread::spawn(move || {
+        let mut all = String::new();
+        if let Some(stdout) = stdout {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                on_line(&line);
+                all.push_str(&line);
+                all.push('\n');
+            }
+        }
+        all
+    });
+    let stderr = child.stderr.take();
+    let stderr_reader = thread::spawn(move || {
+        let mut all = String::new();
+        if let Some(mut stderr) = stderr {
+            let _ = stderr.read_to_string(&mut all);
+        }
+        all
+    });
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(CommandOutput {
+                status,
+                stdout: stdout_reader.join().unwrap_or_default(),
+                stderr: stderr_reader.join().unwrap_or_default(),
+            }));
+        }
+        if progress.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
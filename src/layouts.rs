@@ -0,0 +1,54 @@
+// Scene layouts: named arrangements of enabled characters that can be recalled at once
+use crate::character_lib::Character;
+use crate::playback::Speed;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Shortcut slots; slot `n` is recalled with cmd+`n`.
+pub const SHORTCUT_SLOTS: std::ops::RangeInclusive<u8> = 1..=9;
+
+/// Where one character instance sits in a layout, and how it plays there.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayoutEntry {
+    pub instance: Uuid,
+    #[serde(default)]
+    pub window_pos: Option<[f32; 2]>,
+    pub scale: f32,
+    pub speed: Speed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneLayout {
+    pub name: String,
+    /// Instances enabled in this layout; every other instance is disabled
+    /// when it's recalled.
+    pub entries: Vec<LayoutEntry>,
+    #[serde(default)]
+    pub shortcut: Option<u8>,
+}
+
+impl SceneLayout {
+    /// The enabled instances as they are now.
+    pub fn capture(name: &str, characters: &[Character]) -> Self {
+        let entries = characters
+            .iter()
+            .flat_map(|c| &c.instances)
+            .filter(|i| i.enabled)
+            .map(|i| LayoutEntry {
+                instance: i.id,
+                window_pos: i.window_pos,
+                scale: i.scale,
+                speed: i.speed,
+            })
+            .collect();
+        Self {
+            name: name.to_string(),
+            entries,
+            shortcut: None,
+        }
+    }
+
+    pub fn entry(&self, instance: Uuid) -> Option<&LayoutEntry> {
+        self.entries.iter().find(|e| e.instance == instance)
+    }
+}
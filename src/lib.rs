@@ -1,6 +1,23 @@
 #[cfg(feature = "steamcmd")]
 pub mod steamlib;
 
+pub mod apng;
+pub mod cache;
 pub mod character_lib;
+pub mod convert;
+pub mod jobs;
+pub mod layouts;
 pub mod settings;
+pub mod sniff;
+pub mod store;
 pub mod logging;
+pub mod media_info;
+pub mod migrations;
+pub mod pack;
+pub mod paths;
+pub mod persist;
+pub mod playback;
+pub mod player;
+pub mod profiles;
+pub mod redraw;
+pub mod relink;
@@ -1,10 +1,30 @@
-use animamac::character_lib::CharacterLibrary;
+use animamac::cache;
+use animamac::character_lib::{
+    CharacterLibrary, ImportError, ImportOptions, OptimizeReport, PreparedImport,
+};
+use animamac::convert::{ConvertOptions, QualityPreset};
+use animamac::jobs::JobQueue;
+use animamac::layouts::SHORTCUT_SLOTS;
 use animamac::logging::log_to_file;
+use animamac::pack::{PackError, PackImport};
+use animamac::player::{self, FrameCache, FrameStream};
+use animamac::playback::{
+    self, AfterLoops, Direction, PlaybackOptions, Playhead, Shown, Speed, Timeline,
+};
+use animamac::profiles;
+use animamac::redraw::{self, RedrawRate};
+use animamac::relink::{MatchKind, RelinkReport};
 use animamac::settings::AppSettings;
+use animamac::sniff::sniff_file;
 #[cfg(feature = "steamcmd")]
 use animamac::steamlib::{extract_workshop_id, get_ws, workshop_dl, DownloadResult};
-use eframe::egui::{self, Color32, Frame, ImageSource};
-use std::collections::HashMap;
+use eframe::egui::load::SizedTexture;
+use eframe::egui::{self, Color32, Frame, ImageSource, TextureOptions};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use uuid::Uuid;
 #[cfg(feature = "lite")]
 use rfd::FileDialog;
 fn main() -> eframe::Result<()> {
@@ -25,9 +45,33 @@ fn main() -> eframe::Result<()> {
     eframe::run_native(
         "AnimaMac",
         options,
-        Box::new(|cc| {
-            egui_extras::install_image_loaders(&cc.egui_ctx);
+        Box::new(|_cc| {
             let mut app = AtApp::default();
+            // A damaged library matters more, so its warning replaces this one.
+            if let Some(warning) = app.settings.load_warning.take() {
+                app.set_status(warning, true);
+            }
+            match app.library.load_warning.take() {
+                Some(warning) => app.set_status(warning, true),
+                // Only collect once the library is known to be complete, or
+                // entries a damaged file still points at would be deleted.
+                None => {
+                    let current = &app.settings.active_profile;
+                    if let Some(referenced) = profiles::referenced_paths(current, &app.library) {
+                        animamac::cache::collect_garbage(&referenced);
+                    }
+                }
+            }
+            app.library.refresh_media_info();
+            app.check_missing();
+            app.resume_optimize_review();
+            if !app.missing.is_empty() && app.status.is_none() {
+                let text = format!(
+                    "{} characters have missing files, see \"Missing Files\"",
+                    app.missing.len()
+                );
+                app.set_status(text, true);
+            }
             #[cfg(feature = "steamcmd")]
             {
                 app.ws = get_ws();
@@ -37,8 +81,655 @@ fn main() -> eframe::Result<()> {
     )
 }
 
+/// State of one character window. Windows are drawn on their own schedule,
+/// so this is shared with the window behind a mutex, and what the window
+/// changes for the rest of the app is queued in `events` for the main window.
 struct CharacterUiState {
     show_settings: bool,
+    playhead: Playhead,
+    /// Shown while the frame that's due is still being decoded.
+    last_frame: Option<usize>,
+    /// Window style has been applied to the native window.
+    configured: bool,
+    redraws: RedrawRate,
+    pending_move: Option<PendingMove>,
+    events: Vec<WindowEvent>,
+}
+
+impl CharacterUiState {
+    fn new(instance: Uuid) -> Self {
+        Self {
+            show_settings: false,
+            playhead: Playhead::new(instance.as_u128()),
+            last_frame: None,
+            configured: false,
+            redraws: RedrawRate::default(),
+            pending_move: None,
+            events: Vec::new(),
+        }
+    }
+}
+
+type SharedUiState = Arc<Mutex<CharacterUiState>>;
+
+/// Something a character window did that the main window acts on.
+enum WindowEvent {
+    Closed,
+    Activated,
+    ToggleMainWindow,
+    RecallLayout(u8),
+    Settings { speed: Speed, scale: f32 },
+    Playback(PlaybackOptions),
+    RemoveCopy,
+    Moved([f32; 2]),
+}
+
+/// How long informational status messages stay visible; errors stay until dismissed.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(6);
+
+struct StatusMessage {
+    text: String,
+    is_error: bool,
+    shown_at: Instant,
+}
+
+const MEDIA_EXTENSIONS: &[&str] = &["png", "apng", "webp", "gif", "mp4", "m4v", "mov", "webm"];
+
+/// How often the UI checks on running jobs.
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A character window being moved by a layout recall. Its reported position
+/// isn't saved until it arrives, or it would overwrite the layout's.
+struct PendingMove {
+    target: [f32; 2],
+    started: Instant,
+    sent: bool,
+}
+
+/// Gives up waiting for a window that the OS placed somewhere else.
+const LAYOUT_MOVE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The layout shortcut slot pressed this frame, if any (cmd+1 to cmd+9).
+fn layout_shortcut(ctx: &egui::Context) -> Option<u8> {
+    const KEYS: [egui::Key; 9] = [
+        egui::Key::Num1,
+        egui::Key::Num2,
+        egui::Key::Num3,
+        egui::Key::Num4,
+        egui::Key::Num5,
+        egui::Key::Num6,
+        egui::Key::Num7,
+        egui::Key::Num8,
+        egui::Key::Num9,
+    ];
+    ctx.input_mut(|i| {
+        SHORTCUT_SLOTS
+            .zip(KEYS)
+            .find(|(_, key)| i.consume_key(egui::Modifiers::COMMAND, *key))
+            .map(|(slot, _)| slot)
+    })
+}
+
+/// Decoding of one character's file for the windows showing it.
+struct AssetStream {
+    stream: FrameStream,
+    /// Asked for and not back yet.
+    pending: HashSet<usize>,
+    error: Option<String>,
+    /// Windows that asked for frames, redrawn as each one arrives.
+    viewers: Arc<Mutex<HashSet<egui::ViewportId>>>,
+}
+
+/// Frames of the characters on screen as textures, decoded just ahead of
+/// where each window is and shared by every copy of a character.
+struct Player {
+    cache: FrameCache<egui::TextureHandle>,
+    streams: HashMap<Uuid, AssetStream>,
+}
+
+impl Player {
+    fn new(budget_mb: u32) -> Self {
+        Self {
+            cache: FrameCache::new(budget_mb as usize * 1024 * 1024),
+            streams: HashMap::new(),
+        }
+    }
+
+    fn set_budget_mb(&mut self, budget_mb: u32) {
+        self.cache.set_budget(budget_mb as usize * 1024 * 1024);
+    }
+
+    /// Uploads whatever the decoders have finished since the last frame.
+    fn receive(&mut self, ctx: &egui::Context) {
+        for (asset, entry) in &mut self.streams {
+            while let Some(result) = entry.stream.try_recv() {
+                let frame = match result {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        entry.error = Some(e);
+                        entry.pending.clear();
+                        continue;
+                    }
+                };
+                entry.pending.remove(&frame.index);
+                let (width, height) = frame.image.dimensions();
+                let image = egui::ColorImage::from_rgba_unmultiplied(
+                    [width as usize, height as usize],
+                    frame.image.as_raw(),
+                );
+                let name = format!("frame:{}#{}", asset, frame.index);
+                let texture = ctx.load_texture(name, image, TextureOptions::LINEAR);
+                let bytes = player::frame_bytes(width, height);
+                self.cache.insert(*asset, frame.index, texture, bytes);
+            }
+        }
+    }
+
+    /// The first of `wanted` if it's ready. Any of them that aren't are
+    /// asked for, in order, and the asking window is redrawn when they come.
+    fn frame(
+        &mut self,
+        ctx: &egui::Context,
+        asset: Uuid,
+        path: &str,
+        wanted: &[usize],
+    ) -> Option<SizedTexture> {
+        if self.streams.get(&asset).is_some_and(|e| e.stream.path() != Path::new(path)) {
+            // Relinked or re-encoded since.
+            self.streams.remove(&asset);
+            self.cache.remove_asset(asset);
+        }
+        let entry = self.streams.entry(asset).or_insert_with(|| {
+            let viewers: Arc<Mutex<HashSet<egui::ViewportId>>> = Arc::default();
+            let wake_viewers = viewers.clone();
+            let ctx = ctx.clone();
+            let wake = move || {
+                for viewer in wake_viewers.lock().unwrap().iter() {
+                    ctx.request_repaint_of(*viewer);
+                }
+            };
+            AssetStream {
+                stream: FrameStream::spawn(path, wake),
+                pending: HashSet::new(),
+                error: None,
+                viewers,
+            }
+        });
+        if entry.error.is_some() {
+            return None;
+        }
+        entry.viewers.lock().unwrap().insert(ctx.viewport_id());
+        let missing: Vec<usize> = wanted
+            .iter()
+            .copied()
+            .filter(|&i| !self.cache.contains(asset, i) && !entry.pending.contains(&i))
+            .collect();
+        entry.pending.extend(&missing);
+        entry.stream.request(missing);
+
+        let index = *wanted.first()?;
+        self.cached(asset, index)
+    }
+
+    fn cached(&mut self, asset: Uuid, index: usize) -> Option<SizedTexture> {
+        self.cache.get(asset, index).map(SizedTexture::from_handle)
+    }
+
+    /// Why `path` can't be played, if it can't. The failed stream is kept
+    /// until the path changes, so it isn't decoded again every frame.
+    fn error(&self, asset: Uuid, path: &str) -> Option<String> {
+        let entry = self.streams.get(&asset)?;
+        if entry.stream.path() != Path::new(path) {
+            return None;
+        }
+        entry.error.clone()
+    }
+
+    /// Stops decoding and frees the frames of characters no longer on screen.
+    fn retain(&mut self, on_screen: &HashSet<Uuid>) {
+        let cache = &mut self.cache;
+        self.streams.retain(|asset, _| {
+            let keep = on_screen.contains(asset);
+            if !keep {
+                cache.remove_asset(*asset);
+            }
+            keep
+        });
+    }
+}
+
+/// What a background job sends back to the UI thread when it's done.
+enum JobOutput {
+    Import(Result<PreparedImport, ImportError>),
+    Optimize(OptimizeReport),
+    PackExport(Result<usize, PackError>),
+    PackImport(Result<PackImport, PackError>),
+    Relink(RelinkReport),
+    #[cfg(feature = "steamcmd")]
+    Download(Option<DownloadResult>),
+}
+
+/// An "optimize library" result waiting for the user. Once applied the
+/// characters use the new files, but the originals stay until confirmed.
+struct OptimizeReview {
+    report: OptimizeReport,
+    applied: bool,
+}
+
+/// "just now", "5 min ago", "3 h ago" or "2 days ago".
+fn format_age(time: SystemTime) -> String {
+    let secs = time.elapsed().map(|d| d.as_secs()).unwrap_or(0);
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", secs / 60),
+        3600..86400 => format!("{} h ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.0} KB", bytes as f64 / 1024.0)
+    }
+}
+
+/// A file waiting on the import options dialog.
+struct PendingImport {
+    path: String,
+    is_video: bool,
+    options: ImportOptions,
+}
+
+/// Checkbox plus drag value for an optional setting, `default` is used when it gets enabled.
+/// Returns whether the value changed.
+fn optional_value<T: egui::emath::Numeric>(
+    ui: &mut egui::Ui,
+    label: &str,
+    value: &mut Option<T>,
+    default: T,
+    range: std::ops::RangeInclusive<T>,
+) -> bool {
+    ui.horizontal(|ui| {
+        let mut enabled = value.is_some();
+        let mut changed = ui.checkbox(&mut enabled, label).changed();
+        if changed {
+            *value = enabled.then_some(default);
+        }
+        if let Some(v) = value {
+            changed |= ui
+                .add(egui::DragValue::new(v).range(range).speed(0.1))
+                .changed();
+        }
+        changed
+    })
+    .inner
+}
+
+/// Draws one character window. Runs whenever that window is due, on its own
+/// schedule; what it changes for the rest of the app goes in `state.events`.
+fn show_character_window(
+    ctx: &egui::Context,
+    snapshot: &CharacterSnapshot,
+    scaled_size: egui::Vec2,
+    state: &mut CharacterUiState,
+    player: &Mutex<Player>,
+) {
+    let CharacterSnapshot {
+        id,
+        character_id,
+        instance_count,
+        name,
+        path,
+        missing,
+        speed,
+        scale,
+        playback,
+        window_pos,
+        frame_delays_ms,
+        info_summary,
+        fps_cap,
+        show_redraw_rate,
+        ..
+    } = snapshot;
+
+    if ctx.input(|i| i.viewport().close_requested()) {
+        state.events.push(WindowEvent::Closed);
+        return;
+    }
+
+    let focused = ctx.input(|i| i.viewport().focused.unwrap_or(false));
+    if ctx.input(|i| i.pointer.any_pressed()) && !focused {
+        ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+    }
+    if focused && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Escape)) {
+        state.show_settings = !state.show_settings;
+        ctx.request_repaint();
+    }
+    if focused && let Some(slot) = layout_shortcut(ctx) {
+        state.events.push(WindowEvent::RecallLayout(slot));
+    }
+    if focused && ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::M)) {
+        state.events.push(WindowEvent::ToggleMainWindow);
+    }
+
+    // Sending these every pass would keep the window redrawing.
+    if !state.configured {
+        ctx.send_viewport_cmd(egui::ViewportCommand::Transparent(true));
+        ctx.send_viewport_cmd(egui::ViewportCommand::Decorations(false));
+        state.configured = true;
+    }
+    state.redraws.record(Instant::now());
+
+    let timeline = Timeline::new(frame_delays_ms, *speed, playback);
+    let now = Duration::from_secs_f64(ctx.input(|i| i.time));
+    let (shown, wanted) = match &timeline {
+        Some(timeline) => {
+            state.playhead.tick(now);
+            let ahead = player::PREFETCH_FRAMES + 1;
+            let shown = state.playhead.shown(timeline, playback);
+            (shown, state.playhead.upcoming(timeline, playback, ahead))
+        }
+        // Still images.
+        None => {
+            let first = Shown::Frame {
+                index: 0,
+                until_next: None,
+            };
+            (first, vec![0])
+        }
+    };
+
+    egui::CentralPanel::default()
+        .frame(Frame::NONE)
+        .show(ctx, |ui| {
+            let capture_id = egui::Id::new(format!("focus-capture:{}", id));
+            let capture = ui.interact(ui.max_rect(), capture_id, egui::Sense::click());
+            if capture.clicked() {
+                state.events.push(WindowEvent::Activated);
+                ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            }
+            if *missing {
+                ui.colored_label(
+                    Color32::from_rgb(230, 120, 100),
+                    format!("⚠ {}: file not found\n{}", name, path),
+                );
+                return;
+            }
+            let mut player = player.lock().unwrap();
+            player.receive(ctx);
+            if let Some(error) = player.error(*character_id, path) {
+                ui.colored_label(
+                    Color32::from_rgb(230, 120, 100),
+                    format!("⚠ {}: {}", name, error),
+                );
+                return;
+            }
+            let Shown::Frame { index, until_next } = shown else {
+                return;
+            };
+            if let Some(delay) = until_next {
+                ctx.request_repaint_after(redraw::paced(delay, *fps_cap));
+            }
+            let frame = match player.frame(ctx, *character_id, path, &wanted) {
+                Some(frame) => {
+                    state.last_frame = Some(index);
+                    Some(frame)
+                }
+                None => state
+                    .last_frame
+                    .and_then(|last| player.cached(*character_id, last)),
+            };
+            if let Some(frame) = frame {
+                let img = egui::Image::new(ImageSource::Texture(frame));
+                ui.add(img.max_size(scaled_size));
+            }
+        });
+
+    if state.show_settings {
+        let settings_frame =
+            egui::Frame::default().fill(Color32::from_rgba_premultiplied(30, 27, 25, 240));
+        egui::Area::new(egui::Id::new(format!("settings:{}", id)))
+            .anchor(egui::Align2::LEFT_TOP, [12.0, 12.0])
+            .show(ctx, |ui| {
+                settings_frame.show(ui, |ui| {
+                    ui.heading(name.clone());
+                    if let Some(summary) = info_summary {
+                        ui.label(summary);
+                    }
+                    ui.separator();
+                    let new_speed = speed_ui(ui, *speed, frame_delays_ms);
+                    if new_speed != *speed {
+                        let settings = WindowEvent::Settings {
+                            speed: new_speed,
+                            scale: *scale,
+                        };
+                        state.events.push(settings);
+                    }
+
+                    ui.separator();
+                    let mut new_scale = *scale;
+                    ui.label("Image Scale");
+                    let slider = egui::Slider::new(&mut new_scale, 0.1f32..=5.0f32).step_by(0.1);
+                    if ui.add(slider).changed() {
+                        let settings = WindowEvent::Settings {
+                            speed: *speed,
+                            scale: new_scale,
+                        };
+                        state.events.push(settings);
+                    }
+
+                    if let Some(timeline) = &timeline {
+                        ui.separator();
+                        transport_ui(ui, &mut state.playhead, timeline, playback);
+                        let mut new_playback = *playback;
+                        let frame_count = frame_delays_ms.len() as u32;
+                        if playback_ui(ui, &mut new_playback, frame_count) {
+                            state.playhead.restart();
+                            state.events.push(WindowEvent::Playback(new_playback));
+                        }
+                    }
+
+                    if *instance_count > 1 {
+                        ui.separator();
+                        if ui.button("Remove this copy").clicked() {
+                            state.events.push(WindowEvent::RemoveCopy);
+                        }
+                    }
+                });
+            });
+    }
+
+    if *show_redraw_rate {
+        let rate = state.redraws.per_second();
+        egui::Area::new(egui::Id::new(format!("redraws:{}", id)))
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-4.0, -4.0])
+            .show(ctx, |ui| {
+                Frame::default()
+                    .fill(Color32::from_black_alpha(160))
+                    .show(ui, |ui| {
+                        ui.colored_label(Color32::WHITE, format!("{}/s", rate));
+                    });
+            });
+    }
+
+    let rect = ctx.input(|i| i.viewport().outer_rect);
+    if let Some(pending) = &mut state.pending_move {
+        if !pending.sent {
+            let [x, y] = pending.target;
+            ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(egui::pos2(x, y)));
+            pending.sent = true;
+        }
+        let arrived = rect.is_some_and(|r| {
+            (r.min.x - pending.target[0]).abs() < 1.0 && (r.min.y - pending.target[1]).abs() < 1.0
+        });
+        if arrived || pending.started.elapsed() >= LAYOUT_MOVE_TIMEOUT {
+            state.pending_move = None;
+        } else {
+            ctx.request_repaint();
+        }
+    } else if let Some(rect) = rect {
+        let pos = [rect.min.x, rect.min.y];
+        if window_pos.map(|p| p != pos).unwrap_or(true) {
+            state.events.push(WindowEvent::Moved(pos));
+        }
+    }
+}
+
+/// Multiplier or fixed framerate, with a slider for the chosen one. Switching
+/// to a fixed framerate starts from the file's average.
+fn speed_ui(ui: &mut egui::Ui, speed: Speed, delays_ms: &[u32]) -> Speed {
+    let mut speed = speed;
+    ui.label("Speed");
+    ui.horizontal(|ui| {
+        let multiplier = matches!(speed, Speed::Multiplier(_));
+        if ui.radio(multiplier, "Multiplier").clicked() && !multiplier {
+            speed = Speed::default();
+        }
+        if ui.radio(!multiplier, "Fixed fps").clicked() && multiplier {
+            let total_ms: u32 = delays_ms.iter().sum();
+            let average = (delays_ms.len() as u32 * 1000).checked_div(total_ms);
+            speed = Speed::Fps(average.unwrap_or(12).clamp(1, playback::MAX_FPS));
+        }
+    });
+    match &mut speed {
+        Speed::Multiplier(multiplier) => {
+            let range = playback::MIN_MULTIPLIER..=playback::MAX_MULTIPLIER;
+            ui.add(egui::Slider::new(multiplier, range).logarithmic(true).suffix("×"));
+        }
+        Speed::Fps(fps) => {
+            ui.add(egui::Slider::new(fps, 1..=playback::MAX_FPS).suffix(" fps"));
+        }
+    }
+    speed
+}
+
+/// Pause/play, step and restart buttons.
+fn transport_ui(
+    ui: &mut egui::Ui,
+    playhead: &mut Playhead,
+    timeline: &Timeline,
+    options: &PlaybackOptions,
+) {
+    ui.horizontal(|ui| {
+        let paused = playhead.is_paused();
+        let label = if paused { "▶ Play" } else { "⏸ Pause" };
+        if ui.button(label).clicked() {
+            playhead.set_paused(!paused);
+        }
+        if ui.button("⏭ Step").on_hover_text("Next frame").clicked() {
+            playhead.step(timeline, options);
+        }
+        if ui.button("⟲ Restart").clicked() {
+            playhead.restart();
+        }
+    });
+}
+
+/// Direction, frame range, loop count and random start. Returns whether
+/// anything changed.
+fn playback_ui(ui: &mut egui::Ui, options: &mut PlaybackOptions, frame_count: u32) -> bool {
+    let before = *options;
+    let last = frame_count.saturating_sub(1);
+    ui.horizontal(|ui| {
+        ui.radio_value(&mut options.direction, Direction::Forward, "Forward");
+        ui.radio_value(&mut options.direction, Direction::Reverse, "Reverse");
+        ui.radio_value(&mut options.direction, Direction::PingPong, "Ping-pong");
+    });
+
+    ui.horizontal(|ui| {
+        let mut limited = options.range.is_some();
+        if ui.checkbox(&mut limited, "Frames").changed() {
+            options.range = limited.then_some([0, last]);
+        }
+        if let Some([first, end]) = &mut options.range {
+            ui.add(egui::DragValue::new(first).range(0..=last));
+            ui.label("to");
+            ui.add(egui::DragValue::new(end).range(0..=last));
+            *first = (*first).min(*end);
+        }
+    });
+
+    ui.horizontal(|ui| {
+        let mut limited = options.loops.is_some();
+        if ui.checkbox(&mut limited, "Play").changed() {
+            options.loops = limited.then_some(1);
+        }
+        if let Some(loops) = &mut options.loops {
+            ui.add(egui::DragValue::new(loops).range(1..=999).suffix("×"));
+            ui.label("then");
+            ui.radio_value(&mut options.after_loops, AfterLoops::Hold, "hold");
+            ui.radio_value(&mut options.after_loops, AfterLoops::Hide, "hide");
+        }
+    });
+
+    ui.checkbox(&mut options.random_start, "Random start frame")
+        .on_hover_text("So copies of the same animation don't move in step");
+    *options != before
+}
+
+/// Preset picker plus the individual conversion settings. Returns whether anything changed.
+fn conversion_options_ui(ui: &mut egui::Ui, id: &str, options: &mut ConvertOptions) -> bool {
+    let mut changed = false;
+
+    let current = QualityPreset::ALL
+        .iter()
+        .find(|preset| preset.options() == *options)
+        .map_or("Custom", |preset| preset.label());
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(current)
+        .show_ui(ui, |ui| {
+            for preset in QualityPreset::ALL {
+                if ui.selectable_label(current == preset.label(), preset.label()).clicked() {
+                    *options = preset.options();
+                    changed = true;
+                }
+            }
+        });
+
+    changed |= ui.checkbox(&mut options.lossless, "Lossless").changed();
+    if !options.lossless {
+        ui.horizontal(|ui| {
+            ui.label("Quality");
+            changed |= ui
+                .add(egui::Slider::new(&mut options.quality, 0.0..=100.0))
+                .changed();
+        });
+    }
+    changed |= optional_value(ui, "Max width", &mut options.max_width, 512, 16..=8192);
+    changed |= optional_value(ui, "Max height", &mut options.max_height, 512, 16..=8192);
+    changed |= optional_value(ui, "Max frame rate", &mut options.max_fps, 30.0, 1.0..=120.0);
+
+    let mut max_mb = options
+        .max_output_bytes
+        .map(|bytes| bytes as f32 / (1024.0 * 1024.0));
+    if optional_value(ui, "Max file size (MB)", &mut max_mb, 2.0, 0.1..=100.0) {
+        options.max_output_bytes = max_mb.map(|mb| (mb * 1024.0 * 1024.0) as u64);
+        changed = true;
+    }
+
+    changed
+}
+
+struct CharacterSnapshot {
+    /// The instance; each has its own window.
+    id: Uuid,
+    character_id: Uuid,
+    instance_count: usize,
+    name: String,
+    path: String,
+    missing: bool,
+    speed: Speed,
+    scale: f32,
+    playback: PlaybackOptions,
+    window_pos: Option<[f32; 2]>,
+    /// Empty for still images.
+    frame_delays_ms: Vec<u32>,
+    aspect_ratio: Option<f32>,
+    info_summary: Option<String>,
+    fps_cap: Option<u32>,
+    show_redraw_rate: bool,
 }
 
 struct AtApp {
@@ -47,9 +738,27 @@ struct AtApp {
     library: CharacterLibrary,
     settings: AppSettings,
     main_visible: bool,
-    active_character: Option<String>,
+    active_character: Option<Uuid>,
     allow_main_close: bool,
-    character_ui: HashMap<String, CharacterUiState>,
+    character_ui: HashMap<Uuid, SharedUiState>,
+    status: Option<StatusMessage>,
+    pending_import: Option<PendingImport>,
+    jobs: JobQueue<JobOutput>,
+    optimize_review: Option<OptimizeReview>,
+    profiles: Vec<String>,
+    profile_name: String,
+    layout_name: String,
+    /// Characters ticked for pack export.
+    pack_selection: HashSet<Uuid>,
+    /// Characters whose file wasn't found at the last check.
+    missing: HashSet<Uuid>,
+    /// Files a search found for missing characters by name alone, waiting
+    /// for the user to say whether they're the same media.
+    name_matches: HashMap<Uuid, String>,
+    media_root_input: String,
+    confirm_delete_profile: bool,
+    player: Arc<Mutex<Player>>,
+    main_redraws: RedrawRate,
     #[cfg(feature = "steamcmd")]
     download_result: Option<DownloadResult>,
     #[cfg(feature = "steamcmd")]
@@ -60,17 +769,37 @@ struct AtApp {
 
 impl Default for AtApp {
     fn default() -> Self {
-        let settings = AppSettings::load();
+        let mut settings = AppSettings::load();
+        if !profiles::exists(&settings.active_profile) {
+            settings.active_profile = profiles::DEFAULT_PROFILE.to_string();
+        }
+        let library = profiles::load(&settings.active_profile);
+        let media_root_input = library.media_root.clone().unwrap_or_default();
+        let player = Arc::new(Mutex::new(Player::new(settings.frame_cache_mb)));
 
         Self {
             id: "".to_owned(),
             ws: "".to_owned(),
-            library: CharacterLibrary::load(),
+            library,
             settings,
             main_visible: true,
             active_character: None,
             allow_main_close: false,
             character_ui: HashMap::new(),
+            status: None,
+            pending_import: None,
+            jobs: JobQueue::new(),
+            optimize_review: None,
+            profiles: profiles::list(),
+            profile_name: String::new(),
+            layout_name: String::new(),
+            pack_selection: HashSet::new(),
+            missing: HashSet::new(),
+            name_matches: HashMap::new(),
+            media_root_input,
+            confirm_delete_profile: false,
+            player,
+            main_redraws: RedrawRate::default(),
             #[cfg(feature = "steamcmd")]
             download_result: None,
             #[cfg(feature = "steamcmd")]
@@ -87,10 +816,19 @@ impl eframe::App for AtApp {
     }
 
     fn on_exit(&mut self, _: Option<&eframe::glow::Context>) {
-        self.settings.save();
+        self.jobs.cancel_all();
+        self.library.flush();
+        if let Some(e) = &self.library.save_error {
+            log_to_file(&format!("library: save failed on exit: {}", e));
+        }
+        self.settings.mark_dirty();
+        if let Err(e) = self.settings.flush() {
+            log_to_file(&format!("settings: save failed on exit: {}", e));
+        }
     }
 
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.main_redraws.record(Instant::now());
         if ctx.input(|i| i.viewport().close_requested()) {
             if self.allow_main_close {
                 // Allow app to quit.
@@ -101,32 +839,32 @@ impl eframe::App for AtApp {
             }
         }
 
-        if let Some(active_path) = self.active_character.clone() {
+        if let Some(active_id) = self.active_character {
             let escape = ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Escape));
             if escape {
-                let state = self
-                    .character_ui
-                    .entry(active_path.clone())
-                    .or_insert(CharacterUiState { show_settings: false });
-                state.show_settings = !state.show_settings;
-                let vp_id =
-                    egui::ViewportId::from_hash_of(format!("character:{}", active_path));
+                if let Some(state) = self.character_ui.get(&active_id) {
+                    let mut state = state.lock().unwrap();
+                    state.show_settings = !state.show_settings;
+                }
+                let vp_id = egui::ViewportId::from_hash_of(format!("character:{}", active_id));
                 ctx.request_repaint_of(vp_id);
             }
 
             let cmd_m =
                 ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::M));
             if cmd_m {
-                self.main_visible = !self.main_visible;
-                ctx.send_viewport_cmd(egui::ViewportCommand::Visible(self.main_visible));
-                if self.main_visible {
-                    ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
-                }
+                self.toggle_main_window(ctx);
             }
         }
 
-        if self.main_visible {
-            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+        let mut recall_slot = layout_shortcut(ctx);
+        if let Some(slot) = self.handle_window_events(ctx) {
+            recall_slot = Some(slot);
+        }
+
+        self.handle_job_results();
+        if !self.jobs.is_empty() {
+            ctx.request_repaint_after(JOB_POLL_INTERVAL);
         }
 
         let main_frame =
@@ -135,29 +873,31 @@ impl eframe::App for AtApp {
             .frame(main_frame)
             .show(ctx, |ui| {
                 self.show_dialog(ui);
+                self.show_import_dialog(ui);
+                self.show_optimize_dialog(ui);
                 ui.heading("AnimaMac Settings");
+                if self.settings.show_redraw_rate {
+                    let rate = self.main_redraws.per_second();
+                    ui.weak(format!("Main window: {} redraws/s", rate));
+                }
+                self.show_status(ui);
+                self.show_jobs(ui);
 
                 ui.separator();
                 ui.heading("My Characters");
+                self.show_profile_switcher(ui);
 
                 #[cfg(feature = "lite")]
                 {
                     if ui.add(egui::Button::new("Add from File")).clicked() {
                         let _ = match FileDialog::new()
-                            .add_filter("Animation/Image", &["png", "apng", "webp", "gif"])
+                            .add_filter("Animation/Image/Video", MEDIA_EXTENSIONS)
                             .pick_file()
                         {
                             Some(path) => {
                                 let path_str = path.to_str().unwrap();
                                 println!("Added: {}", path_str);
-                                self.library.add_character(path_str);
-                                if let Some(idx) = self.library.index_by_path(path_str) {
-                                    self.library.set_enabled(idx, true);
-                                    self.character_ui
-                                        .entry(path_str.to_string())
-                                        .or_insert(CharacterUiState { show_settings: false });
-                                    self.active_character = Some(path_str.to_string());
-                                }
+                                self.begin_import(path_str);
                             }
                             None => {}
                         };
@@ -167,18 +907,45 @@ impl eframe::App for AtApp {
                 let char_count = self.library.characters.len();
                 if char_count > 0 {
                     let mut toggle_index: Option<usize> = None;
+                    let mut add_instance_index: Option<usize> = None;
                     let mut remove_index: Option<usize> = None;
                     ui.vertical(|ui| {
                         for (i, char) in self.library.characters.iter().enumerate() {
                             ui.horizontal(|ui| {
-                                let btn_text = if char.enabled {
-                                    format!("✓ {}", char.name)
+                                let missing = self.missing.contains(&char.id);
+                                let btn_text = if missing {
+                                    format!("⚠ {}", char.name)
+                                } else if char.is_enabled() {
+                                    match char.instances.iter().filter(|i| i.enabled).count() {
+                                        1 => format!("✓ {}", char.name),
+                                        n => format!("✓ {} ×{}", char.name, n),
+                                    }
                                 } else {
                                     char.name.clone()
                                 };
-                                if ui.add(egui::Button::new(btn_text)).clicked() {
+                                let mut btn = ui.add(egui::Button::new(btn_text));
+                                if missing {
+                                    let text = format!("File not found: {}", char.path);
+                                    btn = btn.on_hover_text(text);
+                                } else if let Some(info) = &char.media_info {
+                                    let mut text = info.summary();
+                                    let player = self.player.lock().unwrap();
+                                    let bytes = player.cache.asset_bytes(char.id);
+                                    if bytes > 0 {
+                                        let memory = format_bytes(bytes as u64);
+                                        text.push_str(&format!("\n{} of frames in memory", memory));
+                                    }
+                                    btn = btn.on_hover_text(text);
+                                }
+                                if btn.clicked() {
                                     toggle_index = Some(i);
                                 }
+                                let add_btn = egui::Button::new("+")
+                                    .small()
+                                    .min_size(egui::vec2(18.0, 18.0));
+                                if ui.add(add_btn).on_hover_text("Add another copy").clicked() {
+                                    add_instance_index = Some(i);
+                                }
                                 let remove_btn = egui::Button::new("×")
                                     .small()
                                     .min_size(egui::vec2(18.0, 18.0));
@@ -190,28 +957,23 @@ impl eframe::App for AtApp {
                     });
 
                     if let Some(i) = toggle_index {
-                        let enabled = !self.library.characters[i].enabled;
-                        self.library.set_enabled(i, enabled);
-                        let path = self.library.characters[i].path.clone();
-                        if enabled {
-                            self.character_ui
-                                .entry(path)
-                                .or_insert(CharacterUiState { show_settings: false });
-                        } else {
-                            self.character_ui.remove(&path);
-                            if self.active_character.as_deref() == Some(&path) {
-                                self.active_character = None;
-                            }
-                        }
+                        let enabled = !self.library.characters[i].is_enabled();
+                        self.library.set_character_enabled(i, enabled);
+                        self.sync_character_ui();
+                    }
+
+                    if let Some(i) = add_instance_index
+                        && let Some(id) = self.library.add_instance(i)
+                    {
+                        self.sync_character_ui();
+                        self.active_character = Some(id);
                     }
 
                     if let Some(i) = remove_index {
-                        let path = self.library.characters[i].path.clone();
+                        let id = self.library.characters[i].id;
                         self.library.remove_character(i);
-                        self.character_ui.remove(&path);
-                        if self.active_character.as_deref() == Some(&path) {
-                            self.active_character = None;
-                        }
+                        self.missing.remove(&id);
+                        self.sync_character_ui();
                     }
                 } else {
                     ui.label("No characters yet. Add some!");
@@ -226,32 +988,13 @@ impl eframe::App for AtApp {
                         .clicked()
                     {
                         let _ = match FileDialog::new()
-                            .add_filter("Animation/Image", &["png", "apng", "webp", "gif"])
+                            .add_filter("Animation/Image/Video", MEDIA_EXTENSIONS)
                             .pick_file()
                         {
                             Some(path) => {
                                 let path_str = path.to_str().unwrap();
-                                let path_lower = path_str.to_lowercase();
-                                let final_path: String;
-
-                                if path_lower.ends_with(".apng") {
-                                    final_path = match animamac::character_lib::CharacterLibrary::convert_apng_to_webp(path_str) {
-                                        Some(converted) => converted,
-                                        None => path_str.to_string(),
-                                    };
-                                } else {
-                                    final_path = path_str.to_string();
-                                }
-
-                                println!("Selected: {}", final_path);
-                                self.library.add_character(&final_path);
-                                    if let Some(idx) = self.library.index_by_path(&final_path) {
-                                        self.library.set_enabled(idx, true);
-                                    self.character_ui
-                                        .entry(final_path.clone())
-                                        .or_insert(CharacterUiState { show_settings: false });
-                                    self.active_character = Some(final_path);
-                                    }
+                                println!("Selected: {}", path_str);
+                                self.begin_import(path_str);
                             }
                             None => {}
                         };
@@ -267,14 +1010,82 @@ impl eframe::App for AtApp {
                         if id.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))
                         {
                             let extracted_id = extract_workshop_id(&self.id);
-                            self.download_result = workshop_dl(&extracted_id, &self.ws);
-                            if self.download_result.is_none() {
-                                log_to_file("steamlib: workshop download failed; see earlier logs");
-                            }
+                            let ws = self.ws.clone();
+                            self.jobs.spawn(
+                                format!("Downloading workshop item {}", extracted_id),
+                                move |progress| {
+                                    JobOutput::Download(workshop_dl(&extracted_id, &ws, progress))
+                                },
+                            );
                         }
                     });
                 }
 
+                ui.separator();
+                egui::CollapsingHeader::new("Conversion Defaults").show(ui, |ui| {
+                    let mut changed = conversion_options_ui(
+                        ui,
+                        "default_conversion_preset",
+                        &mut self.settings.conversion,
+                    );
+                    changed |= ui
+                        .checkbox(
+                            &mut self.settings.ask_import_options,
+                            "Ask before every import",
+                        )
+                        .changed();
+                    if changed {
+                        self.settings.mark_dirty();
+                    }
+
+                    ui.separator();
+                    let can_optimize =
+                        !self.library.characters.is_empty() && self.optimize_review.is_none();
+                    if ui
+                        .add_enabled(can_optimize, egui::Button::new("Optimize Library"))
+                        .on_hover_text("Re-encode every character with these settings")
+                        .clicked()
+                    {
+                        self.start_optimize();
+                    }
+                });
+
+                egui::CollapsingHeader::new("Layouts").show(ui, |ui| {
+                    self.show_layouts(ui);
+                });
+
+                if !self.missing.is_empty() {
+                    egui::CollapsingHeader::new(format!("Missing Files ({})", self.missing.len()))
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            self.show_missing(ui);
+                        });
+                }
+
+                egui::CollapsingHeader::new("Media Folder").show(ui, |ui| {
+                    self.show_media_root(ui);
+                });
+
+                egui::CollapsingHeader::new("Character Packs").show(ui, |ui| {
+                    self.show_packs(ui);
+                });
+
+                egui::CollapsingHeader::new("Profiles").show(ui, |ui| {
+                    self.show_profiles(ui);
+                });
+
+                egui::CollapsingHeader::new("Performance").show(ui, |ui| {
+                    self.show_performance(ui);
+                });
+
+                egui::CollapsingHeader::new("Memory").show(ui, |ui| {
+                    self.show_memory(ui);
+                });
+
+                egui::CollapsingHeader::new("Backups").show(ui, |ui| {
+                    self.show_backups(ui);
+                });
+
                 ui.separator();
                 if ui.add(egui::Button::new("Exit")).clicked() {
                     self.allow_main_close = true;
@@ -283,28 +1094,45 @@ impl eframe::App for AtApp {
             });
 
         let monitor_size = ctx.input(|i| i.viewport().monitor_size);
-        let characters_snapshot: Vec<(usize, String, String, i64, f32, Option<[f32; 2]>)> = self
+        let fps_cap = self.settings.effective_fps_cap();
+        let show_redraw_rate = self.settings.show_redraw_rate;
+        let characters_snapshot: Vec<CharacterSnapshot> = self
             .library
             .characters
             .iter()
-            .enumerate()
-            .filter(|(_, c)| c.enabled)
-            .map(|(i, c)| {
-                (
-                    i,
-                    c.name.clone(),
-                    c.path.clone(),
-                    c.speed,
-                    c.scale,
-                    c.window_pos,
-                )
+            .flat_map(|c| c.instances.iter().filter(|i| i.enabled).map(move |i| (c, i)))
+            .map(|(c, i)| CharacterSnapshot {
+                id: i.id,
+                character_id: c.id,
+                instance_count: c.instances.len(),
+                name: c.name.clone(),
+                path: c.path.clone(),
+                missing: self.missing.contains(&c.id),
+                speed: i.speed,
+                scale: i.scale,
+                playback: i.playback,
+                window_pos: i.window_pos,
+                frame_delays_ms: c
+                    .media_info
+                    .as_ref()
+                    .map(|m| m.frame_delays_ms.clone())
+                    .unwrap_or_default(),
+                aspect_ratio: c.media_info.as_ref().map(|m| m.aspect_ratio()),
+                info_summary: c.media_info.as_ref().map(|m| m.summary()),
+                fps_cap,
+                show_redraw_rate,
             })
             .collect();
 
-        for (index, name, path, speed, scale, window_pos) in characters_snapshot {
-            let viewport_id = egui::ViewportId::from_hash_of(format!("character:{}", path));
+        let on_screen: HashSet<Uuid> =
+            characters_snapshot.iter().map(|s| s.character_id).collect();
+        self.player.lock().unwrap().retain(&on_screen);
+
+        for snapshot in characters_snapshot {
+            let id = snapshot.id;
+            let viewport_id = egui::ViewportId::from_hash_of(format!("character:{}", id));
             let mut builder = egui::ViewportBuilder::default()
-                .with_title(name.clone())
+                .with_title(snapshot.name.clone())
                 .with_decorations(false)
                 .with_transparent(true)
                 .with_has_shadow(false)
@@ -312,10 +1140,16 @@ impl eframe::App for AtApp {
                 .with_window_level(egui::WindowLevel::AlwaysOnTop)
                 .with_mouse_passthrough(false);
 
-            let scaled_size = egui::vec2(320.0 * scale, 320.0 * scale);
+            // Fit the image's real aspect ratio inside the 320px base box.
+            let base_size = match snapshot.aspect_ratio {
+                Some(aspect) if aspect >= 1.0 => egui::vec2(320.0, 320.0 / aspect),
+                Some(aspect) if aspect > 0.0 => egui::vec2(320.0 * aspect, 320.0),
+                _ => egui::vec2(320.0, 320.0),
+            };
+            let scaled_size = base_size * snapshot.scale;
             builder = builder.with_inner_size([scaled_size.x, scaled_size.y]);
 
-            if let Some(pos) = window_pos {
+            if let Some(pos) = snapshot.window_pos {
                 builder = builder.with_position([pos[0], pos[1]]);
             } else if let Some(monitor_size) = monitor_size {
                 let center_pos = egui::pos2(
@@ -325,116 +1159,1033 @@ impl eframe::App for AtApp {
                 builder = builder.with_position(center_pos);
             }
 
-            let mut show_settings = self
+            let state = self
                 .character_ui
-                .get(&path)
-                .map(|state| state.show_settings)
-                .unwrap_or(false);
-
-            ctx.show_viewport_immediate(viewport_id, builder, |ctx, class| {
+                .entry(id)
+                .or_insert_with(|| Arc::new(Mutex::new(CharacterUiState::new(id))))
+                .clone();
+            let player = self.player.clone();
+            ctx.show_viewport_deferred(viewport_id, builder, move |ctx, class| {
                 assert!(
-                    class == egui::ViewportClass::Immediate,
+                    class == egui::ViewportClass::Deferred,
                     "This egui backend doesn't support multiple viewports"
                 );
-
-                if ctx.input(|i| i.viewport().close_requested()) {
-                    self.library.set_enabled(index, false);
-                    self.character_ui.remove(&path);
-                    return;
+                let mut state = state.lock().unwrap();
+                let queued = state.events.len();
+                show_character_window(ctx, &snapshot, scaled_size, &mut state, &player);
+                if state.events.len() > queued {
+                    ctx.request_repaint_of(egui::ViewportId::ROOT);
                 }
+            });
+        }
+
+        if let Some(slot) = recall_slot {
+            match self.library.layout_for_shortcut(slot) {
+                Some(index) => self.recall_layout(index),
+                None => self.set_status(format!("No layout on cmd+{}", slot), false),
+            }
+        }
 
-                let focused = ctx.input(|i| i.viewport().focused.unwrap_or(false));
-                if ctx.input(|i| i.pointer.any_pressed()) && !focused {
-                    ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+        self.flush_pending_saves(ctx);
+    }
+}
+
+impl AtApp {
+    fn toggle_main_window(&mut self, ctx: &egui::Context) {
+        self.main_visible = !self.main_visible;
+        let visible = egui::ViewportCommand::Visible(self.main_visible);
+        ctx.send_viewport_cmd_to(egui::ViewportId::ROOT, visible);
+        if self.main_visible {
+            ctx.send_viewport_cmd_to(egui::ViewportId::ROOT, egui::ViewportCommand::Focus);
+        }
+    }
+
+    /// Applies what the character windows did since the last pass, returning
+    /// the layout shortcut pressed in one, if any.
+    fn handle_window_events(&mut self, ctx: &egui::Context) -> Option<u8> {
+        let events: Vec<(Uuid, WindowEvent)> = self
+            .character_ui
+            .iter()
+            .flat_map(|(id, state)| {
+                let events = std::mem::take(&mut state.lock().unwrap().events);
+                events.into_iter().map(move |event| (*id, event))
+            })
+            .collect();
+        let mut recall_slot = None;
+        for (id, event) in events {
+            match event {
+                WindowEvent::Closed => {
+                    self.library.set_enabled(id, false);
+                    self.character_ui.remove(&id);
                 }
-                if focused && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Escape)) {
-                    show_settings = !show_settings;
-                    if let Some(state) = self.character_ui.get_mut(&path) {
-                        state.show_settings = show_settings;
+                WindowEvent::Activated => self.active_character = Some(id),
+                WindowEvent::ToggleMainWindow => self.toggle_main_window(ctx),
+                WindowEvent::RecallLayout(slot) => recall_slot = Some(slot),
+                WindowEvent::Settings { speed, scale } => {
+                    self.library.update_settings(id, speed, scale);
+                }
+                WindowEvent::Playback(playback) => self.library.update_playback(id, playback),
+                WindowEvent::RemoveCopy => {
+                    if self.library.remove_instance(id) {
+                        self.sync_character_ui();
                     }
-                    ctx.request_repaint();
                 }
+                WindowEvent::Moved(pos) => self.library.update_position(id, pos),
+            }
+        }
+        recall_slot
+    }
 
-                if focused
-                    && ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::M))
-                {
-                    self.main_visible = !self.main_visible;
-                    ctx.send_viewport_cmd_to(
-                        egui::ViewportId::ROOT,
-                        egui::ViewportCommand::Visible(self.main_visible),
-                    );
-                    if self.main_visible {
-                        ctx.send_viewport_cmd_to(
-                            egui::ViewportId::ROOT,
-                            egui::ViewportCommand::Focus,
+    fn set_status(&mut self, text: impl Into<String>, is_error: bool) {
+        self.status = Some(StatusMessage {
+            text: text.into(),
+            is_error,
+            shown_at: Instant::now(),
+        });
+    }
+
+    fn show_status(&mut self, ui: &mut egui::Ui) {
+        let Some(status) = &self.status else {
+            return;
+        };
+        let elapsed = status.shown_at.elapsed();
+        if !status.is_error && elapsed >= STATUS_TIMEOUT {
+            self.status = None;
+            return;
+        }
+
+        let (fill, prefix) = if status.is_error {
+            (Color32::from_rgb(110, 32, 32), "⚠ ")
+        } else {
+            (Color32::from_rgb(40, 70, 45), "")
+        };
+        let mut dismiss = false;
+        egui::Frame::default()
+            .fill(fill)
+            .inner_margin(6.0)
+            .corner_radius(4.0)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}{}", prefix, status.text));
+                    if ui.small_button("×").on_hover_text("Dismiss").clicked() {
+                        dismiss = true;
+                    }
+                });
+            });
+        if dismiss {
+            self.status = None;
+        } else if !status.is_error {
+            ui.ctx().request_repaint_after(STATUS_TIMEOUT - elapsed);
+        }
+    }
+
+    fn default_import_options(&self) -> ImportOptions {
+        ImportOptions {
+            convert: self.settings.conversion.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Starts importing a picked file; videos (or every file, if the user asked for it)
+    /// go through the options dialog first.
+    fn begin_import(&mut self, path: &str) {
+        let is_video = matches!(sniff_file(Path::new(path)), Ok(Some(s)) if s.format.is_video());
+        let options = self.default_import_options();
+        if is_video || self.settings.ask_import_options {
+            self.pending_import = Some(PendingImport {
+                path: path.to_string(),
+                is_video,
+                options,
+            });
+        } else {
+            self.import_character(path, None, &options);
+        }
+    }
+
+    fn show_import_dialog(&mut self, ui: &mut egui::Ui) {
+        let Some(pending) = self.pending_import.as_mut() else {
+            return;
+        };
+
+        let mut import = false;
+        let mut cancel = false;
+        let dialog_frame =
+            egui::Frame::default().fill(Color32::from_rgba_premultiplied(30, 27, 25, 255));
+        egui::Area::new("import_dialog".into())
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ui.ctx(), |ui| {
+                dialog_frame.show(ui, |ui| {
+                    ui.heading(if pending.is_video {
+                        "Import Video"
+                    } else {
+                        "Import"
+                    });
+                    ui.separator();
+                    ui.label(&pending.path);
+                    ui.separator();
+
+                    if pending.is_video {
+                        let options = &mut pending.options.video;
+                        ui.horizontal(|ui| {
+                            ui.label("Start at (s)");
+                            ui.add(
+                                egui::DragValue::new(&mut options.start_secs)
+                                    .range(0.0..=3600.0)
+                                    .speed(0.1),
+                            );
+                        });
+                        optional_value(
+                            ui,
+                            "End at (s)",
+                            &mut options.end_secs,
+                            5.0,
+                            0.0..=3600.0,
+                        );
+                        optional_value(
+                            ui,
+                            "Max duration (s)",
+                            &mut options.max_duration_secs,
+                            10.0,
+                            0.1..=600.0,
                         );
+                        optional_value(ui, "Frame rate", &mut options.fps, 15.0, 1.0..=60.0);
+                        ui.separator();
                     }
-                }
 
-                ctx.send_viewport_cmd(egui::ViewportCommand::Transparent(true));
-                ctx.send_viewport_cmd(egui::ViewportCommand::Decorations(false));
-
-                egui::CentralPanel::default()
-                    .frame(Frame::NONE)
-                    .show(ctx, |ui| {
-                        let capture_id =
-                            egui::Id::new(format!("focus-capture:{}", path));
-                        let capture =
-                            ui.interact(ui.max_rect(), capture_id, egui::Sense::click());
-                        if capture.clicked() {
-                            self.active_character = Some(path.clone());
-                            ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+                    conversion_options_ui(
+                        ui,
+                        "import_conversion_preset",
+                        &mut pending.options.convert,
+                    );
+
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if ui.button("Convert & Add").clicked() {
+                            import = true;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            cancel = true;
                         }
-                        let mut img = egui::Image::new(ImageSource::Uri(
-                            std::borrow::Cow::Owned(format!("file://{}", path)),
-                        ));
-                        img.fps = u128::try_from(speed).unwrap_or(0);
-                        img = img.max_size(scaled_size);
-                        ui.add(img);
                     });
+                });
+            });
 
-                if show_settings {
-                    let settings_frame = egui::Frame::default()
-                        .fill(Color32::from_rgba_premultiplied(30, 27, 25, 240));
-                    egui::Area::new(egui::Id::new(format!("settings:{}", path)))
-                        .anchor(egui::Align2::LEFT_TOP, [12.0, 12.0])
-                        .show(ctx, |ui| {
-                            settings_frame.show(ui, |ui| {
-                                ui.heading(name.clone());
-                                ui.separator();
-                                let mut new_speed = speed;
-                                ui.label("Framerate (0 for default)");
-                                if ui
-                                    .add(egui::Slider::new(&mut new_speed, 0..=240))
-                                    .changed()
-                                {
-                                    self.library.update_settings(index, new_speed, scale);
-                                }
+        if import && let Some(pending) = self.pending_import.take() {
+            self.import_character(&pending.path, None, &pending.options);
+        } else if cancel {
+            self.pending_import = None;
+        }
+    }
 
-                                ui.separator();
-                                let mut new_scale = scale;
-                                ui.label("Image Scale");
-                                if ui
-                                    .add(
-                                        egui::Slider::new(&mut new_scale, 0.1f32..=5.0f32)
-                                            .step_by(0.1),
-                                    )
-                                    .changed()
-                                {
-                                    self.library.update_settings(index, speed, new_scale);
-                                }
-                            });
+    /// Starts converting a file on a worker thread; it's added to the library
+    /// once the job finishes.
+    fn import_character(&mut self, path: &str, name: Option<&str>, options: &ImportOptions) {
+        let file_name = Path::new(path)
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string());
+        let path = path.to_string();
+        let name = name.map(str::to_string);
+        let options = options.clone();
+        self.jobs
+            .spawn(format!("Importing {}", file_name), move |progress| {
+                let result =
+                    CharacterLibrary::prepare_import(&path, name.as_deref(), &options, progress);
+                if let Err(e) = &result {
+                    log_to_file(&format!("import failed for {}: {}", path, e));
+                }
+                JobOutput::Import(result)
+            });
+    }
+
+    fn handle_job_results(&mut self) {
+        for (_, output) in self.jobs.poll() {
+            match output {
+                JobOutput::Import(result) => {
+                    let result = result.and_then(|prepared| self.library.add_prepared(prepared));
+                    self.finish_import(result);
+                }
+                JobOutput::Optimize(report) => {
+                    if report.entries.is_empty() {
+                        self.set_status("Nothing to optimize, no character got smaller", false);
+                    } else {
+                        self.optimize_review = Some(OptimizeReview {
+                            report,
+                            applied: false,
                         });
+                    }
+                }
+                JobOutput::PackExport(result) => match result {
+                    Ok(count) => self.set_status(format!("Exported {} characters", count), false),
+                    Err(PackError::Cancelled) => self.set_status("Export cancelled", false),
+                    Err(e) => self.set_status(format!("Export failed: {}", e), true),
+                },
+                JobOutput::Relink(report) => self.finish_relink(report),
+                JobOutput::PackImport(result) => match result {
+                    Ok(import) => self.finish_pack_import(import),
+                    Err(PackError::Cancelled) => self.set_status("Import cancelled", false),
+                    Err(e) => self.set_status(format!("Pack import failed: {}", e), true),
+                },
+                #[cfg(feature = "steamcmd")]
+                JobOutput::Download(result) => {
+                    if result.is_none() {
+                        log_to_file("steamlib: workshop download failed; see earlier logs");
+                        self.set_status(
+                            "Workshop download failed or was cancelled, see AnimaMac.log for details",
+                            true,
+                        );
+                    }
+                    self.download_result = result;
+                }
+            }
+        }
+    }
+
+    fn finish_pack_import(&mut self, import: PackImport) {
+        let added = self.library.add_unpacked(&import);
+        let mut text = format!("Added {} characters from the pack", added);
+        if !import.duplicates.is_empty() {
+            text.push_str(&format!(
+                ", skipped {} already in the library ({})",
+                import.duplicates.len(),
+                import.duplicates.join(", ")
+            ));
+        }
+        self.set_status(text, false);
+    }
+
+    /// Character checkboxes for export, plus export and import buttons.
+    fn show_packs(&mut self, ui: &mut egui::Ui) {
+        if self.library.characters.is_empty() {
+            ui.label("No characters to export.");
+        }
+        // Forget characters that were removed since being ticked.
+        self.pack_selection
+            .retain(|id| self.library.index_by_id(*id).is_some());
+        for character in &self.library.characters {
+            let mut selected = self.pack_selection.contains(&character.id);
+            if ui.checkbox(&mut selected, &character.name).changed() {
+                if selected {
+                    self.pack_selection.insert(character.id);
+                } else {
+                    self.pack_selection.remove(&character.id);
+                }
+            }
+        }
+
+        #[cfg(feature = "lite")]
+        ui.horizontal(|ui| {
+            let count = self.pack_selection.len();
+            if ui
+                .add_enabled(count > 0, egui::Button::new(format!("Export {} Selected", count)))
+                .clicked()
+                && let Some(output) = FileDialog::new()
+                    .add_filter("Character pack", &[animamac::pack::PACK_EXTENSION])
+                    .set_file_name(format!("characters.{}", animamac::pack::PACK_EXTENSION))
+                    .save_file()
+            {
+                let characters: Vec<_> = self
+                    .library
+                    .characters
+                    .iter()
+                    .filter(|c| self.pack_selection.contains(&c.id))
+                    .cloned()
+                    .collect();
+                self.jobs.spawn("Exporting character pack", move |progress| {
+                    let result = animamac::pack::export_pack(&characters, &output, progress);
+                    JobOutput::PackExport(result)
+                });
+            }
+            if ui.button("Import Pack").clicked()
+                && let Some(input) = FileDialog::new()
+                    .add_filter("Character pack", &[animamac::pack::PACK_EXTENSION])
+                    .pick_file()
+            {
+                let existing = self.library.characters.clone();
+                let label = format!(
+                    "Importing {}",
+                    input.file_name().unwrap_or_default().to_string_lossy()
+                );
+                self.jobs.spawn(label, move |progress| {
+                    let result = animamac::pack::import_pack(&input, &existing, progress);
+                    JobOutput::PackImport(result)
+                });
+            }
+        });
+    }
+
+    /// Enables a newly imported character and reports the outcome in the status area.
+    fn finish_import(&mut self, result: Result<usize, ImportError>) {
+        let index = match result {
+            Ok(index) => {
+                let char_name = self.library.characters[index].name.clone();
+                self.set_status(format!("Added \"{}\"", char_name), false);
+                index
+            }
+            Err(ImportError::Duplicate { index, name }) => {
+                self.set_status(format!("\"{}\" is already in the library", name), false);
+                index
+            }
+            Err(ImportError::Cancelled) => {
+                self.set_status("Import cancelled", false);
+                return;
+            }
+            Err(e) => {
+                self.set_status(e.to_string(), true);
+                return;
+            }
+        };
+
+        self.library.set_character_enabled(index, true);
+        self.sync_character_ui();
+        self.active_character = self.library.characters[index].instances.first().map(|i| i.id);
+    }
+
+    /// Writes library and settings changes once they've settled, and makes
+    /// sure a frame comes around to do it when the UI is otherwise idle.
+    fn flush_pending_saves(&mut self, ctx: &egui::Context) {
+        self.library.flush_if_due();
+        if let Some(error) = self.library.save_error.take() {
+            self.set_status(error, true);
+        }
+        if let Err(e) = self.settings.flush_if_due() {
+            log_to_file(&format!("settings: save failed: {}", e));
+            self.set_status(format!("Could not save settings: {}", e), true);
+        }
+
+        let due_in = [self.library.flush_due_in(), self.settings.flush_due_in()]
+            .into_iter()
+            .flatten()
+            .min();
+        if let Some(due_in) = due_in {
+            ctx.request_repaint_after(due_in);
+        }
+    }
+
+    /// Lists library.json backups, each with a button to restore it.
+    fn show_backups(&mut self, ui: &mut egui::Ui) {
+        let backups = self.library.backups();
+        if backups.is_empty() {
+            ui.label("No backups yet, one is taken when the library is saved, at most hourly.");
+            return;
+        }
+
+        let mut restore = None;
+        for (n, modified) in backups {
+            ui.horizontal(|ui| {
+                ui.label(format!("Backup {} · {}", n, format_age(modified)));
+                if ui.small_button("Restore").clicked() {
+                    restore = Some(n);
                 }
+            });
+        }
 
-                if let Some(rect) = ctx.input(|i| i.viewport().outer_rect) {
-                    let pos = [rect.min.x, rect.min.y];
-                    if window_pos.map(|p| p != pos).unwrap_or(true) {
-                        self.library.update_position(index, pos);
+        if let Some(n) = restore {
+            match self.library.restore_backup(n) {
+                Ok(mut library) => {
+                    let warning = library.load_warning.take();
+                    self.library = library;
+                    self.library_replaced();
+                    match warning {
+                        Some(warning) => self.set_status(warning, true),
+                        None => {
+                            let text = format!("Restored the library from backup {}", n);
+                            self.set_status(text, false);
+                        }
                     }
                 }
+                Err(e) => {
+                    log_to_file(&format!("library: restoring backup {} failed: {}", n, e));
+                    self.set_status(format!("Could not restore backup {}: {}", n, e), true);
+                }
+            }
+        }
+    }
+
+    /// Enables the layout's characters and moves their windows into place.
+    fn recall_layout(&mut self, index: usize) {
+        let moved = self.library.apply_layout(index);
+        self.sync_character_ui();
+
+        let started = Instant::now();
+        for id in moved {
+            let Some(target) = self.library.instance(id).and_then(|i| i.window_pos) else {
+                continue;
+            };
+            let Some(state) = self.character_ui.get(&id) else {
+                continue;
+            };
+            state.lock().unwrap().pending_move = Some(PendingMove {
+                target,
+                started,
+                sent: false,
             });
         }
+
+        let name = self.library.layouts[index].name.clone();
+        self.set_status(format!("Recalled layout \"{}\"", name), false);
+    }
+
+    /// Keeps per-window UI state in step with which instances are enabled.
+    fn sync_character_ui(&mut self) {
+        let enabled: HashSet<Uuid> = self
+            .library
+            .characters
+            .iter()
+            .flat_map(|c| &c.instances)
+            .filter(|i| i.enabled)
+            .map(|i| i.id)
+            .collect();
+        self.character_ui.retain(|id, _| enabled.contains(id));
+        for id in enabled {
+            self.character_ui
+                .entry(id)
+                .or_insert_with(|| Arc::new(Mutex::new(CharacterUiState::new(id))));
+        }
+        if let Some(active) = &self.active_character
+            && !self.character_ui.contains_key(active)
+        {
+            self.active_character = None;
+        }
+    }
+
+    /// Saved layouts with recall, update, shortcut and remove controls.
+    fn show_layouts(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut self.layout_name);
+            let name = self.layout_name.trim().to_string();
+            if ui
+                .add_enabled(!name.is_empty(), egui::Button::new("Save Current"))
+                .on_hover_text("Saves which characters are enabled, with position, scale and speed")
+                .clicked()
+            {
+                self.library.save_layout(&name);
+                self.layout_name.clear();
+                self.set_status(format!("Saved layout \"{}\"", name), false);
+            }
+        });
+
+        if self.library.layouts.is_empty() {
+            ui.label("No layouts yet.");
+            return;
+        }
+
+        let mut recall = None;
+        let mut update = None;
+        let mut remove = None;
+        let mut shortcut_change = None;
+        for (i, layout) in self.library.layouts.iter().enumerate() {
+            ui.horizontal(|ui| {
+                let label = format!("{} ({})", layout.name, layout.entries.len());
+                if ui.button(label).on_hover_text("Recall").clicked() {
+                    recall = Some(i);
+                }
+                let mut shortcut = layout.shortcut;
+                let shortcut_text = match shortcut {
+                    Some(slot) => format!("cmd+{}", slot),
+                    None => "No shortcut".to_string(),
+                };
+                egui::ComboBox::from_id_salt(("layout_shortcut", i))
+                    .selected_text(shortcut_text)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut shortcut, None, "No shortcut");
+                        for slot in SHORTCUT_SLOTS {
+                            ui.selectable_value(&mut shortcut, Some(slot), format!("cmd+{}", slot));
+                        }
+                    });
+                if shortcut != layout.shortcut {
+                    shortcut_change = Some((i, shortcut));
+                }
+                if ui
+                    .small_button("Update")
+                    .on_hover_text("Replace with the current arrangement")
+                    .clicked()
+                {
+                    update = Some(layout.name.clone());
+                }
+                if ui.small_button("×").on_hover_text("Remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+
+        if let Some((i, shortcut)) = shortcut_change {
+            self.library.set_layout_shortcut(i, shortcut);
+        }
+        if let Some(name) = update {
+            self.library.save_layout(&name);
+            self.set_status(format!("Updated layout \"{}\"", name), false);
+        }
+        if let Some(i) = remove {
+            self.library.remove_layout(i);
+        } else if let Some(i) = recall {
+            self.recall_layout(i);
+        }
+    }
+
+    /// Redraw cap, power saver and the redraw rate overlay.
+    fn show_performance(&mut self, ui: &mut egui::Ui) {
+        let mut changed = optional_value(
+            ui,
+            "Limit redraws to (fps)",
+            &mut self.settings.fps_cap,
+            redraw::DEFAULT_FPS_CAP,
+            1..=240,
+        );
+        let text = format!("Power saver (at most {} fps)", redraw::POWER_SAVER_FPS_CAP);
+        changed |= ui
+            .checkbox(&mut self.settings.power_saver, text)
+            .on_hover_text("Animations keep their speed but show fewer frames")
+            .changed();
+        changed |= ui
+            .checkbox(&mut self.settings.show_redraw_rate, "Show redraw rate")
+            .changed();
+        if changed {
+            self.settings.mark_dirty();
+        }
+    }
+
+    /// Frame cache use per character, and its size limit.
+    fn show_memory(&mut self, ui: &mut egui::Ui) {
+        let mut player = self.player.lock().unwrap();
+        let cache = &player.cache;
+        ui.label(format!(
+            "Animation frames: {} of {}",
+            format_bytes(cache.used_bytes() as u64),
+            format_bytes(cache.budget() as u64)
+        ));
+        for character in &self.library.characters {
+            let bytes = cache.asset_bytes(character.id);
+            if bytes > 0 {
+                ui.label(format!("{}: {}", character.name, format_bytes(bytes as u64)));
+            }
+        }
+
+        let limit = egui::Slider::new(&mut self.settings.frame_cache_mb, 32..=2048)
+            .logarithmic(true)
+            .suffix(" MB")
+            .text("Limit");
+        if ui.add(limit).changed() {
+            player.set_budget_mb(self.settings.frame_cache_mb);
+            self.settings.mark_dirty();
+        }
+    }
+
+    /// Resets per-character UI state after a different library was loaded.
+    fn library_replaced(&mut self) {
+        self.character_ui.clear();
+        self.active_character = None;
+        self.resume_optimize_review();
+        self.media_root_input = self.library.media_root.clone().unwrap_or_default();
+        self.check_missing();
+    }
+
+    fn check_missing(&mut self) {
+        self.missing = self
+            .library
+            .missing_characters()
+            .into_iter()
+            .map(|i| self.library.characters[i].id)
+            .collect();
+        self.name_matches.retain(|id, _| self.missing.contains(id));
+    }
+
+    /// Characters whose file is gone, with a search to find them again.
+    fn show_missing(&mut self, ui: &mut egui::Ui) {
+        let mut relink = None;
+        let mut skip = None;
+        for character in &self.library.characters {
+            if !self.missing.contains(&character.id) {
+                continue;
+            }
+            ui.label(&character.name).on_hover_text(&character.path);
+            if let Some(found) = self.name_matches.get(&character.id) {
+                ui.horizontal(|ui| {
+                    ui.weak("Same name, different or unknown content:")
+                        .on_hover_text(found);
+                    if ui.small_button("Use").clicked() {
+                        relink = Some((character.id, found.clone()));
+                    }
+                    if ui.small_button("Skip").clicked() {
+                        skip = Some(character.id);
+                    }
+                });
+            }
+        }
+        if let Some((id, path)) = relink {
+            self.library.relink(id, &path);
+            self.name_matches.remove(&id);
+            self.check_missing();
+        }
+        if let Some(id) = skip {
+            self.name_matches.remove(&id);
+        }
+        ui.horizontal(|ui| {
+            #[cfg(feature = "lite")]
+            if ui
+                .button("Search Folder")
+                .on_hover_text("Looks in a folder and its subfolders, by file name and content")
+                .clicked()
+                && let Some(folder) = FileDialog::new().pick_folder()
+            {
+                let missing: Vec<_> = self
+                    .library
+                    .characters
+                    .iter()
+                    .filter(|c| self.missing.contains(&c.id))
+                    .map(animamac::relink::MissingFile::from_character)
+                    .collect();
+                self.jobs.spawn("Searching for missing files", move |progress| {
+                    JobOutput::Relink(animamac::relink::search(&folder, &missing, progress))
+                });
+            }
+            if ui.button("Check Again").clicked() {
+                self.check_missing();
+            }
+        });
+    }
+
+    /// Relinks the characters found by content. Those found by name alone
+    /// are listed under "Missing Files" for the user to confirm.
+    fn finish_relink(&mut self, report: RelinkReport) {
+        let mut relinked = 0;
+        for found in report.matches {
+            if found.kind == MatchKind::Name {
+                self.name_matches.insert(found.id, found.new_path);
+            } else {
+                self.library.relink(found.id, &found.new_path);
+                relinked += 1;
+            }
+        }
+        self.check_missing();
+
+        let mut text = format!("Relinked {} characters", relinked);
+        if !self.name_matches.is_empty() {
+            text.push_str(&format!(
+                ", {} found by file name only, see \"Missing Files\"",
+                self.name_matches.len()
+            ));
+        }
+        if !report.not_found.is_empty() {
+            text.push_str(&format!(", not found: {}", report.not_found.join(", ")));
+        }
+        self.set_status(text, !report.not_found.is_empty());
+    }
+
+    /// The folder character paths are stored relative to.
+    fn show_media_root(&mut self, ui: &mut egui::Ui) {
+        let root = animamac::character_lib::media_root_dir(self.library.media_root.as_deref());
+        ui.label(format!("Paths are stored relative to {}", root.display()));
+        ui.weak("Leave empty for the data folder. ~ stands for your home folder.");
+        let mut apply = None;
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.media_root_input);
+            #[cfg(feature = "lite")]
+            if ui.button("Choose").clicked()
+                && let Some(folder) = FileDialog::new().pick_folder()
+            {
+                apply = Some(folder.to_string_lossy().to_string());
+            }
+            if ui.button("Apply").clicked() {
+                apply = Some(self.media_root_input.trim().to_string());
+            }
+        });
+
+        if let Some(root) = apply {
+            self.media_root_input = root.clone();
+            let relinked = self.library.set_media_root(Some(root));
+            self.check_missing();
+            let text = match relinked {
+                0 => "Media folder changed".to_string(),
+                n => format!("Media folder changed, found {} missing files there", n),
+            };
+            self.set_status(text, false);
+        }
+    }
+
+    /// Profiles can't change under a running import or an optimize review,
+    /// whose results belong to the library they started from.
+    fn can_switch_profile(&self) -> bool {
+        self.jobs.is_empty() && self.optimize_review.is_none()
+    }
+
+    /// Saves the current library and opens another profile's. Character
+    /// viewports are only shown for the open library, so the old ones close
+    /// on the next frame and the new profile's enabled characters open where
+    /// they were left.
+    fn switch_profile(&mut self, name: &str) {
+        self.library.flush();
+        if let Some(error) = self.library.save_error.take() {
+            self.set_status(error, true);
+            return;
+        }
+        let mut library = profiles::load(name);
+        let warning = library.load_warning.take();
+        library.refresh_media_info();
+        self.library = library;
+        self.library_replaced();
+        self.confirm_delete_profile = false;
+        self.settings.active_profile = name.to_string();
+        self.settings.mark_dirty();
+        match warning {
+            Some(warning) => self.set_status(warning, true),
+            None => self.set_status(format!("Switched to profile \"{}\"", name), false),
+        }
+    }
+
+    fn show_profile_switcher(&mut self, ui: &mut egui::Ui) {
+        if self.profiles.len() < 2 {
+            return;
+        }
+        let mut selected = self.settings.active_profile.clone();
+        ui.add_enabled_ui(self.can_switch_profile(), |ui| {
+            egui::ComboBox::from_label("Profile")
+                .selected_text(&selected)
+                .show_ui(ui, |ui| {
+                    for name in &self.profiles {
+                        ui.selectable_value(&mut selected, name.clone(), name);
+                    }
+                });
+        });
+        if selected != self.settings.active_profile {
+            self.switch_profile(&selected);
+        }
+    }
+
+    /// Creating, cloning, renaming and deleting profiles. Each action uses the
+    /// name typed in the field; clone, rename and delete act on the current profile.
+    fn show_profiles(&mut self, ui: &mut egui::Ui) {
+        let current = self.settings.active_profile.clone();
+        ui.label(format!("Current profile: {}", current));
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut self.profile_name);
+        });
+
+        let name = self.profile_name.trim().to_string();
+        let is_default = current == profiles::DEFAULT_PROFILE;
+        let can_switch = self.can_switch_profile();
+        let mut result = None;
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(can_switch && !name.is_empty(), egui::Button::new("New"))
+                .on_hover_text("Create an empty profile and switch to it")
+                .clicked()
+            {
+                result = Some(profiles::create(&name));
+            }
+            if ui
+                .add_enabled(can_switch && !name.is_empty(), egui::Button::new("Clone"))
+                .on_hover_text("Copy the current profile's characters into a new profile")
+                .clicked()
+            {
+                self.library.flush();
+                result = Some(profiles::clone_profile(&current, &name));
+            }
+            if ui
+                .add_enabled(
+                    can_switch && !name.is_empty() && !is_default,
+                    egui::Button::new("Rename"),
+                )
+                .clicked()
+            {
+                self.library.flush();
+                result = Some(profiles::rename(&current, &name));
+            }
+        });
+
+        let delete_label = if self.confirm_delete_profile {
+            format!("Really delete \"{}\"?", current)
+        } else {
+            "Delete Profile".to_string()
+        };
+        if ui
+            .add_enabled(can_switch && !is_default, egui::Button::new(delete_label))
+            .on_hover_text("Converted files stay in the cache until garbage collection")
+            .clicked()
+        {
+            if self.confirm_delete_profile {
+                self.confirm_delete_profile = false;
+                match profiles::delete(&current) {
+                    Ok(()) => {
+                        self.profiles = profiles::list();
+                        // Don't write the deleted library back out on the way.
+                        self.library = CharacterLibrary::empty(profiles::store_for(&current));
+                        self.switch_profile(profiles::DEFAULT_PROFILE);
+                        self.set_status(format!("Deleted profile \"{}\"", current), false);
+                    }
+                    Err(e) => self.set_status(e.to_string(), true),
+                }
+            } else {
+                self.confirm_delete_profile = true;
+            }
+        }
+
+        match result {
+            Some(Ok(())) => {
+                self.profiles = profiles::list();
+                self.profile_name.clear();
+                // New and clone open the new profile; a rename reopens the
+                // same library from its new directory.
+                self.switch_profile(&name);
+            }
+            Some(Err(e)) => self.set_status(e.to_string(), true),
+            None => {}
+        }
+    }
+
+    fn start_optimize(&mut self) {
+        let characters = self.library.characters.clone();
+        let options = self.settings.conversion.clone();
+        self.jobs.spawn("Optimizing library", move |progress| {
+            JobOutput::Optimize(CharacterLibrary::optimize(&characters, &options, progress))
+        });
+    }
+
+    /// Brings back the review of an optimization that was applied but not yet
+    /// confirmed or reverted when the app quit or the library was switched.
+    fn resume_optimize_review(&mut self) {
+        let entries = self.library.pending_optimization.clone();
+        self.optimize_review = (!entries.is_empty()).then(|| OptimizeReview {
+            report: OptimizeReport {
+                entries,
+                skipped: vec![],
+            },
+            applied: true,
+        });
+    }
+
+    fn show_optimize_dialog(&mut self, ui: &mut egui::Ui) {
+        let Some(review) = &self.optimize_review else {
+            return;
+        };
+
+        let mut apply = false;
+        let mut discard = false;
+        let mut confirm = false;
+        let mut revert = false;
+        let dialog_frame =
+            egui::Frame::default().fill(Color32::from_rgba_premultiplied(30, 27, 25, 255));
+        egui::Area::new("optimize_dialog".into())
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ui.ctx(), |ui| {
+                dialog_frame.show(ui, |ui| {
+                    let report = &review.report;
+                    ui.heading("Optimize Library");
+                    ui.separator();
+                    egui::ScrollArea::vertical().max_height(240.0).show(ui, |ui| {
+                        for entry in &report.entries {
+                            ui.label(format!(
+                                "{}: {} → {}",
+                                entry.name,
+                                format_bytes(entry.bytes_before),
+                                format_bytes(entry.bytes_after)
+                            ));
+                        }
+                        for (name, reason) in &report.skipped {
+                            ui.weak(format!("{}: skipped, {}", name, reason));
+                        }
+                    });
+                    ui.separator();
+                    ui.label(format!(
+                        "Total: {} → {}",
+                        format_bytes(report.bytes_before()),
+                        format_bytes(report.bytes_after())
+                    ));
+
+                    ui.horizontal(|ui| {
+                        if review.applied {
+                            ui.label(
+                                "Now using the optimized files. Originals are kept until you confirm.",
+                            );
+                        } else if ui.button("Apply").clicked() {
+                            apply = true;
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        if review.applied {
+                            if ui
+                                .button("Keep & Remove Originals")
+                                .on_hover_text("Only files in the app cache are deleted")
+                                .clicked()
+                            {
+                                confirm = true;
+                            }
+                            if ui.button("Revert").clicked() {
+                                revert = true;
+                            }
+                        } else if ui.button("Discard").clicked() {
+                            discard = true;
+                        }
+                    });
+                });
+            });
+
+        if apply && let Some(review) = self.optimize_review.as_mut() {
+            self.library.apply_optimization(&review.report);
+            review.applied = true;
+        } else if revert && let Some(review) = self.optimize_review.take() {
+            self.library.revert_optimization(&review.report);
+            self.set_status("Reverted to the original files", false);
+        } else if confirm && let Some(review) = self.optimize_review.take() {
+            self.library.confirm_optimization();
+            let current = &self.settings.active_profile;
+            let removed = match profiles::referenced_paths(current, &self.library) {
+                Some(referenced) => review
+                    .report
+                    .entries
+                    .iter()
+                    .filter(|e| {
+                        cache::remove_if_unreferenced(Path::new(&e.original_path), &referenced)
+                    })
+                    .count(),
+                // Another profile couldn't be read; leave the originals to garbage collection.
+                None => 0,
+            };
+            log_to_file(&format!("optimize: removed {} original cache files", removed));
+            self.set_status(
+                format!(
+                    "Library optimized, saved {}",
+                    format_bytes(
+                        review
+                            .report
+                            .bytes_before()
+                            .saturating_sub(review.report.bytes_after())
+                    )
+                ),
+                false,
+            );
+        } else if discard {
+            // The unused conversions are left for cache garbage collection.
+            self.optimize_review = None;
+        }
+    }
+
+    /// Running jobs with their progress and a cancel button each.
+    fn show_jobs(&mut self, ui: &mut egui::Ui) {
+        let mut cancel = None;
+        for job in self.jobs.jobs() {
+            ui.horizontal(|ui| {
+                ui.label(&job.label);
+                if ui.small_button("Cancel").clicked() {
+                    cancel = Some(job.id);
+                }
+            });
+            let stage = job.progress.stage();
+            let bar = match job.progress.fraction() {
+                Some(fraction) => egui::ProgressBar::new(fraction)
+                    .text(format!("{} ({:.0}%)", stage, fraction * 100.0)),
+                None if job.progress.done() > 0 => egui::ProgressBar::new(0.0)
+                    .animate(true)
+                    .text(format!("{} ({} frames)", stage, job.progress.done())),
+                None => egui::ProgressBar::new(0.0).animate(true).text(stage),
+            };
+            ui.add(bar);
+        }
+        if let Some(id) = cancel {
+            self.jobs.cancel(id);
+        }
     }
 }
 
@@ -471,19 +2222,15 @@ impl AtApp {
                             if ui.button("Add to Library & Use").clicked() {
                                 if let Some(first_file) = result_files.first() {
                                     let full_path = format!("{}/{}", result_path, first_file);
-                                    if self.add_to_library && !self.character_name.is_empty() {
-                                        self.library
-                                            .add_named_character(&full_path, &self.character_name);
+                                    let name = if self.add_to_library
+                                        && !self.character_name.is_empty()
+                                    {
+                                        Some(self.character_name.clone())
                                     } else {
-                                        self.library.add_character(&full_path);
-                                    }
-                                    if let Some(idx) = self.library.index_by_path(&full_path) {
-                                        self.library.set_enabled(idx, true);
-                                        self.character_ui
-                                            .entry(full_path.clone())
-                                            .or_insert(CharacterUiState { show_settings: false });
-                                        self.active_character = Some(full_path);
-                                    }
+                                        None
+                                    };
+                                    let options = self.default_import_options();
+                                    self.import_character(&full_path, name.as_deref(), &options);
                                 }
                                 self.download_result = None;
                                 self.character_name.clear();
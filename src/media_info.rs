@@ -0,0 +1,305 @@
+// Probing of image/animation files: size, frames, timing, looping and alpha
+use crate::sniff::{MediaFormat, sniff_file};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaInfo {
+    pub width: u32,
+    pub height: u32,
+    pub frame_count: u32,
+    /// Per-frame display time in milliseconds, empty for still images.
+    pub frame_delays_ms: Vec<u32>,
+    pub total_duration_ms: u64,
+    /// Number of times the animation plays, 0 means loop forever.
+    pub loop_count: u32,
+    pub has_alpha: bool,
+}
+
+impl MediaInfo {
+    pub fn is_animated(&self) -> bool {
+        self.frame_count > 1
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        if self.height == 0 {
+            1.0
+        } else {
+            self.width as f32 / self.height as f32
+        }
+    }
+
+    /// Short human readable description, e.g. "320×240 · 24 frames · 1.20 s".
+    pub fn summary(&self) -> String {
+        let mut s = format!("{}×{}", self.width, self.height);
+        if self.is_animated() {
+            s.push_str(&format!(
+                " · {} frames · {:.2} s",
+                self.frame_count,
+                self.total_duration_ms as f64 / 1000.0
+            ));
+            if self.loop_count > 0 {
+                s.push_str(&format!(" · plays {}×", self.loop_count));
+            }
+        }
+        if self.has_alpha {
+            s.push_str(" · alpha");
+        }
+        s
+    }
+
+    fn from_delays(
+        width: u32,
+        height: u32,
+        frame_delays_ms: Vec<u32>,
+        loop_count: u32,
+        has_alpha: bool,
+    ) -> Self {
+        let frame_count = frame_delays_ms.len().max(1) as u32;
+        let frame_delays_ms = if frame_count > 1 {
+            frame_delays_ms
+        } else {
+            vec![]
+        };
+        Self {
+            width,
+            height,
+            frame_count,
+            total_duration_ms: frame_delays_ms.iter().map(|&d| d as u64).sum(),
+            frame_delays_ms,
+            loop_count,
+            has_alpha,
+        }
+    }
+}
+
+pub fn probe(path: &Path) -> Result<MediaInfo, String> {
+    let sniffed = sniff_file(path).map_err(|e| e.to_string())?;
+
+    match sniffed.map(|s| s.format) {
+        Some(MediaFormat::Png | MediaFormat::Apng) => probe_png(path),
+        Some(MediaFormat::Gif) => probe_gif(path),
+        Some(MediaFormat::Webp) => probe_webp(path),
+        Some(format @ (MediaFormat::Mp4 | MediaFormat::Mov | MediaFormat::Webm)) => Err(format!(
+            "{} video must be converted before probing",
+            format.extension()
+        )),
+        None => Err(format!("unrecognised file format: {}", path.display())),
+    }
+}
+
+fn probe_png(path: &Path) -> Result<MediaInfo, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let decoder = png::Decoder::new(BufReader::new(file));
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+
+    let info = reader.info();
+    let (width, height) = (info.width, info.height);
+    let has_alpha =
+        info.color_type.samples() == 2 || info.color_type.samples() == 4 || info.trns.is_some();
+
+    let Some(actl) = info.animation_control else {
+        return Ok(MediaInfo::from_delays(width, height, vec![], 0, has_alpha));
+    };
+
+    let mut delays = vec![];
+    // The default image only counts as a frame when an fcTL precedes it.
+    if let Some(fctl) = info.frame_control {
+        delays.push(delay_ms(fctl.delay_num, fctl.delay_den));
+    }
+    while (delays.len() as u32) < actl.num_frames {
+        let fctl = reader.next_frame_info().map_err(|e| e.to_string())?;
+        delays.push(delay_ms(fctl.delay_num, fctl.delay_den));
+    }
+
+    Ok(MediaInfo::from_delays(
+        width,
+        height,
+        delays,
+        actl.num_plays,
+        has_alpha,
+    ))
+}
+
+/// Reads sizes and delays from the block headers; no frame is decoded.
+fn probe_gif(path: &Path) -> Result<MediaInfo, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let info =
+        gif_block_info(&bytes).ok_or_else(|| format!("not a readable GIF: {}", path.display()))?;
+    Ok(MediaInfo::from_delays(
+        info.width,
+        info.height,
+        info.frame_delays_ms,
+        gif_loop_count(&bytes),
+        info.has_alpha,
+    ))
+}
+
+/// Reads sizes and delays from the RIFF chunks; no frame is decoded.
+fn probe_webp(path: &Path) -> Result<MediaInfo, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let info = webp_chunk_info(&bytes);
+    // Only extended files have a canvas chunk; simple ones are a single image.
+    let (width, height) = match info.canvas {
+        Some(canvas) => canvas,
+        None => image::image_dimensions(path).map_err(|e| e.to_string())?,
+    };
+    Ok(MediaInfo::from_delays(
+        width,
+        height,
+        info.frame_delays_ms,
+        info.loop_count,
+        info.has_alpha,
+    ))
+}
+
+fn delay_ms(num: u16, den: u16) -> u32 {
+    let den = if den == 0 { 100 } else { den as u32 };
+    num as u32 * 1000 / den
+}
+
+struct GifBlockInfo {
+    width: u32,
+    height: u32,
+    frame_delays_ms: Vec<u32>,
+    has_alpha: bool,
+}
+
+/// Walks a GIF's blocks, taking each image's delay and transparency from the
+/// Graphic Control Extension before it. A file cut short has the images found
+/// before it ends; `None` if it hasn't even a screen descriptor or any image.
+fn gif_block_info(bytes: &[u8]) -> Option<GifBlockInfo> {
+    let header = bytes.get(..13)?;
+    let width = u16::from_le_bytes([header[6], header[7]]) as u32;
+    let height = u16::from_le_bytes([header[8], header[9]]) as u32;
+    let mut pos = 13 + gif_colour_table_len(header[10]);
+
+    let mut frame_delays_ms = vec![];
+    let mut has_alpha = false;
+    let mut delay_ms = 0;
+    while let Some(&block) = bytes.get(pos) {
+        match block {
+            // Extension: introducer, label, then data sub-blocks.
+            0x21 => {
+                // Graphic Control Extension: size (4), flags, delay in 1/100 s,
+                // transparent colour index.
+                if bytes.get(pos + 1) == Some(&0xF9)
+                    && let Some(gce) = bytes.get(pos + 2..pos + 7)
+                {
+                    has_alpha |= gce[1] & 0x01 != 0;
+                    delay_ms = u16::from_le_bytes([gce[2], gce[3]]) as u32 * 10;
+                }
+                pos = gif_skip_sub_blocks(bytes, pos + 2);
+            }
+            // Image descriptor, optional local colour table, LZW code size, data sub-blocks.
+            0x2C => {
+                frame_delays_ms.push(std::mem::take(&mut delay_ms));
+                let Some(&flags) = bytes.get(pos + 9) else {
+                    break;
+                };
+                pos += 10 + gif_colour_table_len(flags);
+                pos = gif_skip_sub_blocks(bytes, pos + 1);
+            }
+            // Trailer, or something that isn't a GIF block.
+            _ => break,
+        }
+    }
+    if frame_delays_ms.is_empty() {
+        return None;
+    }
+    Some(GifBlockInfo {
+        width,
+        height,
+        frame_delays_ms,
+        has_alpha,
+    })
+}
+
+fn gif_colour_table_len(flags: u8) -> usize {
+    if flags & 0x80 != 0 {
+        3 << ((flags & 0x07) + 1)
+    } else {
+        0
+    }
+}
+
+fn gif_skip_sub_blocks(bytes: &[u8], mut pos: usize) -> usize {
+    while let Some(&size) = bytes.get(pos) {
+        pos += 1;
+        if size == 0 {
+            break;
+        }
+        pos += size as usize;
+    }
+    pos
+}
+
+/// Reads the NETSCAPE2.0 application extension; GIFs without one play once and
+/// a repeat count of N means N + 1 plays.
+pub(crate) fn gif_loop_count(bytes: &[u8]) -> u32 {
+    const APP_ID: &[u8] = b"NETSCAPE2.0";
+    let Some(pos) = bytes.windows(APP_ID.len()).position(|w| w == APP_ID) else {
+        return 1;
+    };
+    match bytes.get(pos + APP_ID.len()..pos + APP_ID.len() + 4) {
+        Some([3, 1, lo, hi]) => match u16::from_le_bytes([*lo, *hi]) as u32 {
+            0 => 0,
+            repeats => repeats + 1,
+        },
+        _ => 1,
+    }
+}
+
+/// What the RIFF chunks of a WebP file say about it.
+#[derive(Debug, Default)]
+pub(crate) struct WebpChunkInfo {
+    pub loop_count: u32,
+    pub has_alpha: bool,
+    /// From the VP8X chunk, which only extended files have.
+    pub canvas: Option<(u32, u32)>,
+    /// Durations of the ANMF frames, empty for still images.
+    pub frame_delays_ms: Vec<u32>,
+}
+
+/// Reads the RIFF chunks of a WebP file without decoding any image data.
+pub(crate) fn webp_chunk_info(bytes: &[u8]) -> WebpChunkInfo {
+    let mut info = WebpChunkInfo::default();
+    let u24 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], 0]);
+
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let fourcc = &bytes[pos..pos + 4];
+        let size = u32::from_le_bytes([
+            bytes[pos + 4],
+            bytes[pos + 5],
+            bytes[pos + 6],
+            bytes[pos + 7],
+        ]) as usize;
+        let data = &bytes[pos + 8..(pos + 8 + size).min(bytes.len())];
+
+        match fourcc {
+            // Flags, 3 reserved bytes, then the canvas size minus one.
+            b"VP8X" if data.len() >= 10 => {
+                info.has_alpha = data[0] & 0x10 != 0;
+                info.canvas = Some((u24(&data[4..7]) + 1, u24(&data[7..10]) + 1));
+            }
+            b"ANIM" if data.len() >= 6 => {
+                info.loop_count = u16::from_le_bytes([data[4], data[5]]) as u32
+            }
+            // Frame offset and size, then the duration in milliseconds.
+            b"ANMF" if data.len() >= 15 => info.frame_delays_ms.push(u24(&data[12..15])),
+            // Simple lossless file: the alpha hint is bit 28 of the header after the signature.
+            b"VP8L" if data.len() >= 5 => {
+                info.has_alpha =
+                    u32::from_le_bytes([data[1], data[2], data[3], data[4]]) & (1 << 28) != 0
+            }
+            _ => {}
+        }
+
+        pos += 8 + size + (size & 1);
+    }
+
+    info
+}
@@ -0,0 +1,170 @@
+// Schema versions of library.json and the steps that bring older files up to date
+use crate::playback::Speed;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Version written by this build.
+pub const LIBRARY_VERSION: u32 = 5;
+
+/// Reads the version of a library file; files from before versioning count as 0.
+pub fn library_version(value: &Value) -> u32 {
+    value
+        .get("version")
+        .and_then(Value::as_u64)
+        .map(|v| v as u32)
+        .unwrap_or(0)
+}
+
+/// Runs every migration step between the file's version and `LIBRARY_VERSION`.
+/// Files from a newer build are returned unchanged.
+pub fn migrate_library(mut value: Value) -> Result<Value, String> {
+    let mut version = library_version(&value);
+    while version < LIBRARY_VERSION {
+        value = match version {
+            0 => v0_to_v1(value)?,
+            1 => v1_to_v2(value),
+            2 => v2_to_v3(value),
+            3 => v3_to_v4(value),
+            4 => v4_to_v5(value),
+            _ => unreachable!("no migration from library version {}", version),
+        };
+        version += 1;
+        if let Some(object) = value.as_object_mut() {
+            object.insert("version".to_string(), json!(version));
+        }
+    }
+    Ok(value)
+}
+
+/// Unversioned files: either `{"characters": [...]}` as written by AnimaMac and
+/// AnimaTux, or a bare array of characters from early AnimaTux builds.
+fn v0_to_v1(value: Value) -> Result<Value, String> {
+    match value {
+        Value::Array(characters) => {
+            let mut object = Map::new();
+            object.insert("characters".to_string(), Value::Array(characters));
+            Ok(Value::Object(object))
+        }
+        Value::Object(mut object) => {
+            object
+                .entry("characters")
+                .or_insert_with(|| Value::Array(vec![]));
+            Ok(Value::Object(object))
+        }
+        _ => Err("library file is neither an object nor a list of characters".to_string()),
+    }
+}
+
+/// Version 2 allows character paths relative to the library's media root.
+/// Older files only have absolute paths, which stay as they are.
+fn v1_to_v2(value: Value) -> Value {
+    value
+}
+
+/// Version 3 identifies characters by a UUID instead of their path, and
+/// layout entries refer to that UUID.
+fn v2_to_v3(mut value: Value) -> Value {
+    let mut ids = HashMap::new();
+    if let Some(characters) = value.get_mut("characters").and_then(Value::as_array_mut) {
+        for character in characters.iter_mut().filter_map(Value::as_object_mut) {
+            let id = character
+                .entry("id")
+                .or_insert_with(|| json!(Uuid::new_v4().to_string()))
+                .clone();
+            if let Some(path) = character.get("path").and_then(Value::as_str) {
+                ids.insert(path.to_string(), id);
+            }
+        }
+    }
+
+    let layouts = value.get_mut("layouts").and_then(Value::as_array_mut);
+    for layout in layouts.into_iter().flatten() {
+        let Some(entries) = layout.get_mut("entries").and_then(Value::as_array_mut) else {
+            continue;
+        };
+        // Entries for characters that were removed since can't be matched up.
+        entries.retain_mut(|entry| {
+            let id = entry
+                .get("path")
+                .and_then(Value::as_str)
+                .and_then(|path| ids.get(path))
+                .cloned();
+            match (id, entry.as_object_mut()) {
+                (Some(id), Some(entry)) => {
+                    entry.remove("path");
+                    entry.insert("character".to_string(), id);
+                    true
+                }
+                _ => false,
+            }
+        });
+    }
+    value
+}
+
+/// Version 4 splits each character into the media and its on-screen
+/// instances. The existing placement becomes the first instance, which takes
+/// the character's id so layout entries keep pointing at it.
+fn v3_to_v4(mut value: Value) -> Value {
+    const INSTANCE_FIELDS: [&str; 5] = ["enabled", "speed", "scale", "window_pos", "window_size"];
+    if let Some(characters) = value.get_mut("characters").and_then(Value::as_array_mut) {
+        for character in characters.iter_mut().filter_map(Value::as_object_mut) {
+            let id = character
+                .get("id")
+                .cloned()
+                .unwrap_or_else(|| json!(Uuid::new_v4().to_string()));
+            let mut instance = Map::new();
+            instance.insert("id".to_string(), id);
+            for field in INSTANCE_FIELDS {
+                if let Some(value) = character.remove(field) {
+                    instance.insert(field.to_string(), value);
+                }
+            }
+            character
+                .entry("instances")
+                .or_insert_with(|| json!([instance]));
+        }
+    }
+
+    let layouts = value.get_mut("layouts").and_then(Value::as_array_mut);
+    for layout in layouts.into_iter().flatten() {
+        let entries = layout.get_mut("entries").and_then(Value::as_array_mut);
+        for entry in entries
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_object_mut)
+        {
+            if let Some(id) = entry.remove("character") {
+                entry.insert("instance".to_string(), id);
+            }
+        }
+    }
+    value
+}
+
+/// Replaces an integer `speed` (0 for the file's own timing, else a fixed
+/// framerate) with a `Speed`. Also used for version 1 packs.
+pub fn upgrade_speed(object: &mut Value) {
+    if let Some(speed) = object.get_mut("speed")
+        && let Some(fps) = speed.as_i64()
+    {
+        *speed = json!(Speed::from_legacy(fps));
+    }
+}
+
+/// Version 5 stores speed as a multiplier of the file's frame delays or a
+/// fixed framerate, instead of a framerate where 0 meant "as authored".
+fn v4_to_v5(mut value: Value) -> Value {
+    let characters = value.get_mut("characters").and_then(Value::as_array_mut);
+    for character in characters.into_iter().flatten() {
+        let instances = character.get_mut("instances").and_then(Value::as_array_mut);
+        instances.into_iter().flatten().for_each(upgrade_speed);
+    }
+    let layouts = value.get_mut("layouts").and_then(Value::as_array_mut);
+    for layout in layouts.into_iter().flatten() {
+        let entries = layout.get_mut("entries").and_then(Value::as_array_mut);
+        entries.into_iter().flatten().for_each(upgrade_speed);
+    }
+    value
+}
@@ -0,0 +1,320 @@
+// Character packs: library entries and their media in one `.animapack` zip
+//
+// Layout of a pack:
+// - `manifest.json`: a `PackManifest` listing every character.
+// - `media/<sha256>.<ext>`: each media file once, named by its content hash.
+use crate::cache;
+use crate::character_lib::Character;
+use crate::jobs::Progress;
+use crate::media_info::{self, MediaInfo};
+use crate::migrations;
+use crate::playback::Speed;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use tempfile::NamedTempFile;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+pub const PACK_EXTENSION: &str = "animapack";
+const MANIFEST_FILE: &str = "manifest.json";
+/// Bumped when the manifest changes in a way older versions can't read.
+pub const PACK_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackManifest {
+    pub version: u32,
+    /// App and version that wrote the pack, for troubleshooting.
+    #[serde(default)]
+    pub created_by: String,
+    pub characters: Vec<PackEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackEntry {
+    pub name: String,
+    /// Path of the media inside the archive.
+    pub file: String,
+    pub sha256: String,
+    #[serde(default)]
+    pub speed: Speed,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// A pack entry unpacked into the cache, ready for `CharacterLibrary::add_unpacked`.
+#[derive(Debug, Clone)]
+pub struct UnpackedCharacter {
+    pub name: String,
+    pub path: String,
+    pub speed: Speed,
+    pub scale: f32,
+    pub tags: Vec<String>,
+    pub media_info: MediaInfo,
+    pub content_hash: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PackImport {
+    pub characters: Vec<UnpackedCharacter>,
+    /// Names of entries whose media is already in the library.
+    pub duplicates: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum PackError {
+    Io(io::Error),
+    Zip(zip::result::ZipError),
+    InvalidManifest(String),
+    UnsupportedVersion(u32),
+    /// A media file doesn't match the hash in the manifest.
+    CorruptMedia(String),
+    Cancelled,
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackError::Io(e) => write!(f, "I/O error: {}", e),
+            PackError::Zip(e) => write!(f, "Not a valid pack: {}", e),
+            PackError::InvalidManifest(reason) => write!(f, "Invalid pack manifest: {}", reason),
+            PackError::UnsupportedVersion(version) => write!(
+                f,
+                "The pack was made by a newer version (format {}), please update AnimaMac",
+                version
+            ),
+            PackError::CorruptMedia(name) => write!(f, "The media for \"{}\" is damaged", name),
+            PackError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for PackError {}
+
+impl From<io::Error> for PackError {
+    fn from(e: io::Error) -> Self {
+        PackError::Io(e)
+    }
+}
+
+impl From<zip::result::ZipError> for PackError {
+    fn from(e: zip::result::ZipError) -> Self {
+        PackError::Zip(e)
+    }
+}
+
+/// Only plain extensions make it into file names on import.
+fn media_extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .filter(|e| !e.is_empty() && e.chars().all(|c| c.is_ascii_alphanumeric()))
+        .unwrap_or("bin")
+        .to_ascii_lowercase()
+}
+
+/// Writes `characters` and their media to `output`. Characters sharing a
+/// file store it once. Returns the number of characters written.
+pub fn export_pack(
+    characters: &[Character],
+    output: &Path,
+    progress: &Progress,
+) -> Result<usize, PackError> {
+    progress.set_stage("Packing characters");
+    progress.set_total(characters.len() as u64);
+
+    let partial = output.with_extension(format!("{}.partial", PACK_EXTENSION));
+    let result = write_pack(characters, &partial, progress);
+    match result {
+        Ok(count) => {
+            fs::rename(&partial, output)?;
+            Ok(count)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+fn write_pack(
+    characters: &[Character],
+    output: &Path,
+    progress: &Progress,
+) -> Result<usize, PackError> {
+    let mut zip = ZipWriter::new(File::create(output)?);
+    // Media is already compressed; deflating it again only costs time.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut written = HashSet::new();
+    let mut entries = Vec::new();
+
+    for (i, character) in characters.iter().enumerate() {
+        if progress.is_cancelled() {
+            return Err(PackError::Cancelled);
+        }
+        let path = Path::new(&character.path);
+        let sha256 = cache::content_hash(path)?;
+        let file = format!("media/{}.{}", sha256, media_extension(path));
+        if written.insert(file.clone()) {
+            zip.start_file(file.as_str(), stored)?;
+            io::copy(&mut File::open(path)?, &mut zip)?;
+        }
+        entries.push(PackEntry {
+            name: character.name.clone(),
+            file,
+            sha256,
+            speed: character
+                .instances
+                .first()
+                .map_or(Speed::default(), |i| i.speed),
+            scale: character.instances.first().map_or(1.0, |i| i.scale),
+            tags: character.tags.clone(),
+        });
+        progress.set_done(i as u64 + 1);
+    }
+
+    let count = entries.len();
+    let manifest = PackManifest {
+        version: PACK_VERSION,
+        created_by: format!("AnimaMac {}", env!("CARGO_PKG_VERSION")),
+        characters: entries,
+    };
+    let json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| PackError::InvalidManifest(e.to_string()))?;
+    zip.start_file(MANIFEST_FILE, SimpleFileOptions::default())?;
+    zip.write_all(&json)?;
+    zip.finish()?.sync_all()?;
+    Ok(count)
+}
+
+fn read_manifest(archive: &mut ZipArchive<File>) -> Result<PackManifest, PackError> {
+    let mut json = String::new();
+    archive
+        .by_name(MANIFEST_FILE)
+        .map_err(|_| PackError::InvalidManifest(format!("{} is missing", MANIFEST_FILE)))?
+        .read_to_string(&mut json)?;
+    let mut value: serde_json::Value =
+        serde_json::from_str(&json).map_err(|e| PackError::InvalidManifest(e.to_string()))?;
+    let version = value["version"].as_u64().unwrap_or(0) as u32;
+    if version > PACK_VERSION {
+        return Err(PackError::UnsupportedVersion(version));
+    }
+    // Version 1 packs have the old integer speed.
+    if version < 2 {
+        let entries = value["characters"].as_array_mut().into_iter().flatten();
+        entries.for_each(migrations::upgrade_speed);
+    }
+    serde_json::from_value(value).map_err(|e| PackError::InvalidManifest(e.to_string()))
+}
+
+/// Unpacks a pack's media into the cache, skipping entries whose content is
+/// already used by one of `existing` (or earlier in the same pack). Nothing
+/// is added to the library; that's `CharacterLibrary::add_unpacked`. Runs as
+/// a background job.
+pub fn import_pack(
+    pack: &Path,
+    existing: &[Character],
+    progress: &Progress,
+) -> Result<PackImport, PackError> {
+    progress.set_stage("Reading pack");
+    let mut archive = ZipArchive::new(File::open(pack)?)?;
+    let manifest = read_manifest(&mut archive)?;
+
+    progress.set_stage("Checking library");
+    progress.set_total(existing.len() as u64);
+    let mut seen = HashSet::new();
+    for (i, character) in existing.iter().enumerate() {
+        if progress.is_cancelled() {
+            return Err(PackError::Cancelled);
+        }
+        let hash = match &character.content_hash {
+            Some(hash) => Some(hash.clone()),
+            // Missing files can't be duplicates of anything.
+            None => cache::content_hash(Path::new(&character.path)).ok(),
+        };
+        seen.extend(hash);
+        progress.set_done(i as u64 + 1);
+    }
+
+    progress.set_stage("Unpacking characters");
+    progress.set_total(manifest.characters.len() as u64);
+    let dir = cache::cache_dir();
+    fs::create_dir_all(&dir)?;
+
+    let mut result = PackImport::default();
+    for (i, entry) in manifest.characters.into_iter().enumerate() {
+        if progress.is_cancelled() {
+            return Err(PackError::Cancelled);
+        }
+        progress.set_done(i as u64);
+        // The hash becomes a file name, so it must be nothing but a hash.
+        if entry.sha256.len() != 64 || !entry.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(PackError::InvalidManifest(format!(
+                "bad hash for \"{}\"",
+                entry.name
+            )));
+        }
+        // Computed digests are lowercase; manifests may not be.
+        let sha256 = entry.sha256.to_ascii_lowercase();
+        if !seen.insert(sha256.clone()) {
+            result.duplicates.push(entry.name);
+            continue;
+        }
+
+        let extension = media_extension(Path::new(&entry.file));
+        let output = dir.join(format!("{}.{}", sha256, extension));
+        if !output.exists() {
+            // Removed again when dropped, whichever way unpacking fails.
+            let partial = NamedTempFile::new_in(&dir)?;
+            let hash = unpack_file(&mut archive, &entry.file, partial.path())?;
+            if hash != sha256 {
+                return Err(PackError::CorruptMedia(entry.name));
+            }
+            partial.persist(&output).map_err(|e| e.error)?;
+        }
+
+        let media_info =
+            media_info::probe(&output).map_err(|_| PackError::CorruptMedia(entry.name.clone()))?;
+        result.characters.push(UnpackedCharacter {
+            name: entry.name,
+            path: output.to_string_lossy().to_string(),
+            speed: entry.speed,
+            scale: entry.scale,
+            tags: entry.tags,
+            media_info,
+            content_hash: sha256,
+        });
+    }
+    Ok(result)
+}
+
+/// Copies one archive member to `output`, returning the hash of what was written.
+fn unpack_file(
+    archive: &mut ZipArchive<File>,
+    name: &str,
+    output: &Path,
+) -> Result<String, PackError> {
+    let mut member = archive.by_name(name)?;
+    let mut file = File::create(output)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = member.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+    }
+    file.sync_all()?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
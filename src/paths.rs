@@ -0,0 +1,119 @@
+// Where AnimaMac keeps its files, resolved in one place for every platform
+//
+// In order of precedence:
+// - `ANIMAMAC_HOME` puts everything under that directory.
+// - Portable mode: an `animamac.portable` file next to the executable keeps
+//   everything in `AnimaMacData/` beside it.
+// - macOS: ~/Library/Application Support, ~/Library/Caches and ~/Library/Logs.
+// - Linux and other Unixes: the XDG config, data, cache and state directories.
+// - Windows: %APPDATA% and %LOCALAPPDATA%.
+use std::env;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+pub const HOME_ENV: &str = "ANIMAMAC_HOME";
+pub const PORTABLE_MARKER: &str = "animamac.portable";
+const PORTABLE_DIR: &str = "AnimaMacData";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layout {
+    /// Everything under one directory, from `ANIMAMAC_HOME` or portable mode.
+    SingleDir {
+        root: PathBuf,
+        portable: bool,
+    },
+    Platform,
+}
+
+/// Resolved once; the environment and the executable don't move while running.
+pub fn layout() -> &'static Layout {
+    static LAYOUT: OnceLock<Layout> = OnceLock::new();
+    LAYOUT.get_or_init(|| {
+        if let Some(root) = env::var_os(HOME_ENV).filter(|v| !v.is_empty()) {
+            return Layout::SingleDir {
+                root: PathBuf::from(root),
+                portable: false,
+            };
+        }
+        if let Some(exe_dir) = env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|p| p.to_path_buf()))
+            && exe_dir.join(PORTABLE_MARKER).exists()
+        {
+            return Layout::SingleDir {
+                root: exe_dir.join(PORTABLE_DIR),
+                portable: true,
+            };
+        }
+        Layout::Platform
+    })
+}
+
+pub fn is_portable() -> bool {
+    matches!(layout(), Layout::SingleDir { portable: true, .. })
+}
+
+pub fn home_dir() -> PathBuf {
+    dirs::home_dir().unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Name of the per-app directory inside the platform locations; the Linux
+/// build has always been AnimaTux.
+fn app_dir_name() -> &'static str {
+    if cfg!(target_os = "macos") || cfg!(windows) {
+        "AnimaMac"
+    } else {
+        "animatux"
+    }
+}
+
+/// settings.json
+pub fn config_dir() -> PathBuf {
+    match layout() {
+        Layout::SingleDir { root, .. } => root.clone(),
+        Layout::Platform => dirs::config_dir()
+            .unwrap_or_else(|| home_dir().join(".config"))
+            .join(app_dir_name()),
+    }
+}
+
+/// library.json, profiles and converted media.
+pub fn data_dir() -> PathBuf {
+    match layout() {
+        Layout::SingleDir { root, .. } => root.clone(),
+        Layout::Platform => dirs::data_dir()
+            .unwrap_or_else(|| home_dir().join(".local/share"))
+            .join(app_dir_name()),
+    }
+}
+
+/// Scratch space that can be deleted at any time.
+pub fn cache_dir() -> PathBuf {
+    match layout() {
+        Layout::SingleDir { root, .. } => root.join("tmp"),
+        Layout::Platform => dirs::cache_dir()
+            .unwrap_or_else(|| home_dir().join(".cache"))
+            .join(app_dir_name()),
+    }
+}
+
+pub fn log_file() -> PathBuf {
+    let dir = match layout() {
+        Layout::SingleDir { root, .. } => root.join("logs"),
+        Layout::Platform if cfg!(target_os = "macos") => home_dir().join("Library/Logs"),
+        Layout::Platform => dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .unwrap_or_else(|| home_dir().join(".local/state"))
+            .join(app_dir_name()),
+    };
+    dir.join("AnimaMac.log")
+}
+
+/// Where AnimaTux used to keep library.json, checked on first run so its
+/// library can be imported.
+pub fn legacy_animatux_dir() -> Option<PathBuf> {
+    match layout() {
+        Layout::Platform => Some(home_dir().join(".config/animatux")),
+        Layout::SingleDir { .. } => None,
+    }
+}
@@ -0,0 +1,166 @@
+// Crash-safe writes for the JSON files in the data directory, with rotating backups
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tempfile::NamedTempFile;
+
+/// A save happens once changes have stopped for this long...
+pub const SAVE_DEBOUNCE: Duration = Duration::from_millis(750);
+/// ...or at the latest this long after the first unsaved change, so a long drag
+/// still gets written out.
+pub const SAVE_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// How many previous versions are kept next to each file, `library.json.1` being the newest.
+pub const BACKUP_COUNT: usize = 3;
+/// Backups are taken at most this often, so an afternoon of saves doesn't
+/// push every older version out.
+pub const BACKUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Replaces `path` with `contents` so that a crash at any point leaves either
+/// the old or the new file, never a truncated one. The old file becomes backup 1
+/// if the newest backup is older than `BACKUP_INTERVAL`.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_with_backup(path, contents, backup_due(path))
+}
+
+fn backup_due(path: &Path) -> bool {
+    fs::metadata(backup_path(path, 1))
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_none_or(|age| age >= BACKUP_INTERVAL)
+}
+
+fn write_with_backup(path: &Path, contents: &[u8], backup: bool) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+
+    let mut temp = NamedTempFile::new_in(dir)?;
+    temp.write_all(contents)?;
+    temp.as_file().sync_all()?;
+
+    if backup && path.exists() {
+        rotate_backups(path)?;
+    }
+    temp.persist(path).map_err(|e| e.error)?;
+
+    // Make the rename itself durable.
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn rotate_backups(path: &Path) -> io::Result<()> {
+    for n in (1..BACKUP_COUNT).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, n + 1))?;
+        }
+    }
+    // Copy rather than rename so `path` exists until the new version replaces it.
+    let newest = backup_path(path, 1);
+    fs::copy(path, &newest)?;
+    // Some platforms copy the modification time too; the interval counts from now.
+    fs::File::options()
+        .write(true)
+        .open(&newest)?
+        .set_modified(SystemTime::now())?;
+    Ok(())
+}
+
+/// Existing backups of `path`, newest first, with their modification times.
+pub fn backups(path: &Path) -> Vec<(usize, SystemTime)> {
+    (1..=BACKUP_COUNT)
+        .filter_map(|n| {
+            let modified = fs::metadata(backup_path(path, n))
+                .and_then(|m| m.modified())
+                .ok()?;
+            Some((n, modified))
+        })
+        .collect()
+}
+
+/// Puts backup `n` back in place. The current file becomes backup 1, so a
+/// restore can itself be undone.
+pub fn restore_backup(path: &Path, n: usize) -> io::Result<()> {
+    let contents = fs::read(backup_path(path, n))?;
+    write_with_backup(path, &contents, true)
+}
+
+/// Tracks unsaved changes so that bursts of them (dragging a window, moving a
+/// slider) end up as a single write.
+#[derive(Debug, Clone, Default)]
+pub struct SaveTracker {
+    first_change: Option<Instant>,
+    last_change: Option<Instant>,
+    pending_changes: u64,
+    pub stats: WriteStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WriteStats {
+    pub writes: u64,
+    pub changes: u64,
+    pub bytes_written: u64,
+}
+
+impl SaveTracker {
+    pub fn mark_dirty(&mut self) {
+        let now = Instant::now();
+        self.first_change.get_or_insert(now);
+        self.last_change = Some(now);
+        self.pending_changes += 1;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.pending_changes > 0
+    }
+
+    /// Time left until the pending changes should be written, zero if they're due.
+    pub fn due_in(&self) -> Option<Duration> {
+        let (first, last) = (self.first_change?, self.last_change?);
+        let debounced = SAVE_DEBOUNCE.saturating_sub(last.elapsed());
+        let capped = SAVE_MAX_DELAY.saturating_sub(first.elapsed());
+        Some(debounced.min(capped))
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.due_in().is_some_and(|d| d.is_zero())
+    }
+
+    /// Pushes the next attempt back by `SAVE_DEBOUNCE` after a failed write.
+    pub fn record_failure(&mut self) {
+        let now = Instant::now();
+        self.first_change = Some(now);
+        self.last_change = Some(now);
+    }
+
+    /// Marks everything as saved and logs the write in debug builds.
+    pub fn record_write(&mut self, name: &str, bytes: usize, took: Duration) {
+        self.stats.writes += 1;
+        self.stats.changes += self.pending_changes;
+        self.stats.bytes_written += bytes as u64;
+        if cfg!(debug_assertions) {
+            crate::logging::log_to_file(&format!(
+                "{}: wrote {} bytes in {:.1} ms for {} changes ({} writes, {} changes, {} bytes so far)",
+                name,
+                bytes,
+                took.as_secs_f64() * 1000.0,
+                self.pending_changes,
+                self.stats.writes,
+                self.stats.changes,
+                self.stats.bytes_written
+            ));
+        }
+        self.first_change = None;
+        self.last_change = None;
+        self.pending_changes = 0;
+    }
+}
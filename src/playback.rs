@@ -0,0 +1,311 @@
+// Animation timing: which frame is showing, and for how much longer
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub const MIN_MULTIPLIER: f32 = 0.25;
+pub const MAX_MULTIPLIER: f32 = 4.0;
+pub const MAX_FPS: u32 = 240;
+
+/// Frames with a delay this short (usually zero) are shown for
+/// `SHORT_DELAY_SHOWN_AS` instead, as browsers do; otherwise they'd flash by
+/// unseen or stall the loop.
+const SHORT_DELAY: Duration = Duration::from_millis(10);
+const SHORT_DELAY_SHOWN_AS: Duration = Duration::from_millis(100);
+
+/// How long a frame with the file's own `delay` is shown at normal speed.
+pub fn shown_delay(delay: Duration) -> Duration {
+    if delay <= SHORT_DELAY {
+        SHORT_DELAY_SHOWN_AS
+    } else {
+        delay
+    }
+}
+
+/// How fast an animation plays.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", content = "value", rename_all = "snake_case")]
+pub enum Speed {
+    /// Scales every frame's own delay, so holds and pauses keep their rhythm.
+    Multiplier(f32),
+    /// Shows every frame for `1 / fps` seconds, ignoring the file's timing.
+    Fps(u32),
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::Multiplier(1.0)
+    }
+}
+
+impl Speed {
+    /// Reads the old integer speed: 0 meant the file's own timing, anything
+    /// else a fixed framerate.
+    pub fn from_legacy(fps: i64) -> Self {
+        match u32::try_from(fps) {
+            Ok(fps) if fps > 0 => Speed::Fps(fps.min(MAX_FPS)),
+            _ => Speed::default(),
+        }
+    }
+
+    /// How long frame `delay` is shown at this speed.
+    pub fn frame_delay(&self, delay: Duration) -> Duration {
+        match *self {
+            Speed::Multiplier(multiplier) => {
+                let multiplier = multiplier.clamp(MIN_MULTIPLIER, MAX_MULTIPLIER);
+                shown_delay(delay).div_f64(multiplier as f64)
+            }
+            Speed::Fps(fps) => Duration::from_secs(1) / fps.clamp(1, MAX_FPS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    #[default]
+    Forward,
+    Reverse,
+    /// Forward, then back again.
+    PingPong,
+}
+
+/// What's shown once the loop count has run out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AfterLoops {
+    /// Keep showing the frame it ended on.
+    #[default]
+    Hold,
+    Hide,
+}
+
+/// How an instance plays its animation. The default is the file's own
+/// frames, forward, forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PlaybackOptions {
+    #[serde(default)]
+    pub direction: Direction,
+    /// First and last frame played, inclusive and counted from 0; `None` for
+    /// every frame.
+    #[serde(default)]
+    pub range: Option<[u32; 2]>,
+    /// Plays this many times and then stops; `None` loops forever.
+    #[serde(default)]
+    pub loops: Option<u32>,
+    #[serde(default)]
+    pub after_loops: AfterLoops,
+    /// Starts somewhere in the loop instead of the first frame, so copies of
+    /// the same animation don't move in step.
+    #[serde(default)]
+    pub random_start: bool,
+}
+
+/// One pass through an animation: the frames in the order they're shown,
+/// with the time each is shown at the chosen speed.
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    frames: Vec<(usize, Duration)>,
+    total: Duration,
+}
+
+impl Timeline {
+    /// `None` for still images.
+    pub fn new(delays_ms: &[u32], speed: Speed, options: &PlaybackOptions) -> Option<Self> {
+        let last = delays_ms.len().checked_sub(1)?;
+        let [first, end] = options.range.unwrap_or([0, last as u32]);
+        let end = (end as usize).min(last);
+        let first = (first as usize).min(end);
+
+        let order: Vec<usize> = match options.direction {
+            Direction::Forward => (first..=end).collect(),
+            Direction::Reverse => (first..=end).rev().collect(),
+            // The end frames aren't repeated at the turns.
+            Direction::PingPong => (first..=end).chain((first + 1..end).rev()).collect(),
+        };
+        let frames: Vec<(usize, Duration)> = order
+            .into_iter()
+            .map(|i| {
+                let delay = Duration::from_millis(delays_ms[i] as u64);
+                (i, speed.frame_delay(delay))
+            })
+            .collect();
+        let total: Duration = frames.iter().map(|(_, delay)| *delay).sum();
+        if total.is_zero() {
+            return None;
+        }
+        Some(Self { frames, total })
+    }
+
+    /// The frame `elapsed` into a loop of this pass, and how long until the next one.
+    pub fn frame_at(&self, elapsed: Duration) -> (usize, Duration) {
+        let (entry, remaining) = self.entry_at(elapsed);
+        (self.frames[entry].0, remaining)
+    }
+
+    /// Position in `frames` and time left on it.
+    fn entry_at(&self, elapsed: Duration) -> (usize, Duration) {
+        let mut position =
+            Duration::from_nanos((elapsed.as_nanos() % self.total.as_nanos()) as u64);
+        for (entry, (_, delay)) in self.frames.iter().enumerate() {
+            if position < *delay {
+                return (entry, *delay - position);
+            }
+            position -= *delay;
+        }
+        (0, self.frames[0].1)
+    }
+
+    /// The frame `elapsed` into a loop and the `count - 1` shown after it.
+    fn upcoming(&self, elapsed: Duration, count: usize) -> Vec<usize> {
+        let (entry, _) = self.entry_at(elapsed);
+        let mut frames: Vec<usize> = (0..count.min(self.frames.len()))
+            .map(|i| self.frames[(entry + i) % self.frames.len()].0)
+            .collect();
+        // Ping-pong shows most frames twice a pass.
+        let mut seen = std::collections::HashSet::new();
+        frames.retain(|index| seen.insert(*index));
+        frames
+    }
+}
+
+/// The frame showing `elapsed` into a looping animation with the given
+/// per-frame delays, and how long until the next one. `None` for anything
+/// without frames.
+pub fn frame_at(delays_ms: &[u32], speed: Speed, elapsed: Duration) -> Option<(usize, Duration)> {
+    let timeline = Timeline::new(delays_ms, speed, &PlaybackOptions::default())?;
+    Some(timeline.frame_at(elapsed))
+}
+
+/// What an animation shows right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shown {
+    /// `until_next` is `None` while nothing is going to change: paused, or
+    /// held after the last loop.
+    Frame {
+        index: usize,
+        until_next: Option<Duration>,
+    },
+    /// Hidden after the last loop.
+    Nothing,
+}
+
+/// Transport state of one playing instance: how far it has got, and whether
+/// it's paused. Kept in memory only; a restart of the app starts from the top.
+#[derive(Debug, Clone)]
+pub struct Playhead {
+    played: Duration,
+    last_tick: Option<Duration>,
+    paused: bool,
+    /// Picks the random start; stable per instance so it doesn't jump around.
+    seed: u128,
+}
+
+impl Playhead {
+    pub fn new(seed: u128) -> Self {
+        Self {
+            played: Duration::ZERO,
+            last_tick: None,
+            paused: false,
+            seed,
+        }
+    }
+
+    /// Moves the playhead on to `now`, a clock that only goes forward.
+    pub fn tick(&mut self, now: Duration) {
+        if let Some(last) = self.last_tick
+            && !self.paused
+        {
+            self.played += now.saturating_sub(last);
+        }
+        self.last_tick = Some(now);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        // The window may not have been redrawn while paused.
+        self.last_tick = None;
+    }
+
+    pub fn restart(&mut self) {
+        self.played = Duration::ZERO;
+    }
+
+    /// Pauses and moves on to the start of the next frame.
+    pub fn step(&mut self, timeline: &Timeline, options: &PlaybackOptions) {
+        self.set_paused(true);
+        // Nothing to step through once the last loop is done.
+        if !self.is_finished(timeline, options) {
+            let (_, remaining) = timeline.frame_at(self.position(timeline, options));
+            self.played += remaining;
+        }
+    }
+
+    fn end(&self, timeline: &Timeline, options: &PlaybackOptions) -> Option<Duration> {
+        options
+            .loops
+            .and_then(|loops| timeline.total.checked_mul(loops))
+    }
+
+    fn is_finished(&self, timeline: &Timeline, options: &PlaybackOptions) -> bool {
+        self.end(timeline, options)
+            .is_some_and(|end| self.played >= end)
+    }
+
+    fn start(&self, timeline: &Timeline, options: &PlaybackOptions) -> Duration {
+        if options.random_start {
+            Duration::from_nanos((self.seed % timeline.total.as_nanos()) as u64)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    fn position(&self, timeline: &Timeline, options: &PlaybackOptions) -> Duration {
+        self.start(timeline, options) + self.played
+    }
+
+    /// The frame showing now and, while playing, the next `count - 1`, for
+    /// decoding ahead. Empty while hidden.
+    pub fn upcoming(
+        &self,
+        timeline: &Timeline,
+        options: &PlaybackOptions,
+        count: usize,
+    ) -> Vec<usize> {
+        match self.shown(timeline, options) {
+            Shown::Nothing => Vec::new(),
+            Shown::Frame {
+                index,
+                until_next: None,
+            } => vec![index],
+            Shown::Frame { .. } => timeline.upcoming(self.position(timeline, options), count),
+        }
+    }
+
+    pub fn shown(&self, timeline: &Timeline, options: &PlaybackOptions) -> Shown {
+        if let Some(end) = self.end(timeline, options)
+            && self.played >= end
+        {
+            return match options.after_loops {
+                AfterLoops::Hold => {
+                    let last_shown = self.start(timeline, options) + end;
+                    let (index, _) =
+                        timeline.frame_at(last_shown.saturating_sub(Duration::from_nanos(1)));
+                    Shown::Frame {
+                        index,
+                        until_next: None,
+                    }
+                }
+                AfterLoops::Hide => Shown::Nothing,
+            };
+        }
+        let (index, until_next) = timeline.frame_at(self.position(timeline, options));
+        Shown::Frame {
+            index,
+            until_next: (!self.paused).then_some(until_next),
+        }
+    }
+}
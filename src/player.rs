@@ -0,0 +1,357 @@
+// Animation playback without holding whole animations in memory: frames are
+// decoded on a worker thread just ahead of the playhead, and what the UI makes
+// of them is kept in a cache bounded by size
+//
+// Animated formats can only be decoded front to back (frames build on each
+// other), so a frame behind the decoder means starting over from the top.
+use crate::sniff::{MediaFormat, sniff_file};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, Frame, Frames, Rgba, RgbaImage};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use uuid::Uuid;
+
+/// How many frames past the one on screen are decoded ahead of time.
+pub const PREFETCH_FRAMES: usize = 8;
+
+/// Bytes of RGBA pixels for a frame of this size.
+pub fn frame_bytes(width: u32, height: u32) -> usize {
+    width as usize * height as usize * 4
+}
+
+/// Decodes a file's frames in order, starting over when asked for one it has
+/// already passed. Still images have a single frame.
+pub struct FrameDecoder {
+    path: PathBuf,
+    /// Sniffed once; `None` if it couldn't be, and the file is left to the
+    /// generic decoder to open or fail on.
+    format: Option<MediaFormat>,
+    frames: Option<Frames<'static>>,
+    next: usize,
+    /// Known once the decoder has been through the whole file.
+    frame_count: Option<usize>,
+}
+
+impl FrameDecoder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let format = sniff_file(&path).ok().flatten().map(|s| s.format);
+        Self {
+            path,
+            format,
+            frames: None,
+            next: 0,
+            frame_count: None,
+        }
+    }
+
+    fn open(&self) -> Result<Frames<'static>, String> {
+        let reader = || -> Result<BufReader<File>, String> {
+            Ok(BufReader::new(
+                File::open(&self.path).map_err(|e| e.to_string())?,
+            ))
+        };
+        let frames = match self.format {
+            Some(MediaFormat::Png | MediaFormat::Apng) => {
+                let decoder = PngDecoder::new(reader()?).map_err(|e| e.to_string())?;
+                if decoder.is_apng().map_err(|e| e.to_string())? {
+                    let decoder = decoder.apng().map_err(|e| e.to_string())?;
+                    Some(decoder.into_frames())
+                } else {
+                    None
+                }
+            }
+            Some(MediaFormat::Gif) => {
+                let decoder = GifDecoder::new(reader()?).map_err(|e| e.to_string())?;
+                Some(decoder.into_frames())
+            }
+            Some(MediaFormat::Webp) => {
+                let mut decoder = WebPDecoder::new(reader()?).map_err(|e| e.to_string())?;
+                if decoder.has_animation() {
+                    decoder
+                        .set_background_color(Rgba([0, 0, 0, 0]))
+                        .map_err(|e| e.to_string())?;
+                    Some(decoder.into_frames())
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        match frames {
+            Some(frames) => Ok(frames),
+            None => {
+                let image = image::open(&self.path).map_err(|e| e.to_string())?;
+                let frame = Frame::new(image.into_rgba8());
+                Ok(Frames::new(Box::new(std::iter::once(Ok(frame)))))
+            }
+        }
+    }
+
+    /// Index of the frame `next_frame` returns.
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    pub fn rewind(&mut self) {
+        self.frames = None;
+        self.next = 0;
+    }
+
+    /// How many frames the file has, once they've all been decoded.
+    pub fn frame_count(&self) -> Option<usize> {
+        self.frame_count
+    }
+
+    /// The next frame, or `None` past the last one; the decoder then starts
+    /// over from the top.
+    pub fn next_frame(&mut self) -> Result<Option<(usize, RgbaImage)>, String> {
+        if self.frames.is_none() {
+            self.frames = Some(self.open()?);
+            self.next = 0;
+        }
+        let frames = self.frames.as_mut().expect("opened above");
+        match frames.next() {
+            Some(Ok(frame)) => {
+                let index = self.next;
+                self.next += 1;
+                Ok(Some((index, frame.into_buffer())))
+            }
+            Some(Err(e)) => {
+                self.rewind();
+                Err(e.to_string())
+            }
+            None => {
+                self.frame_count = Some(self.next);
+                self.rewind();
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DecodedFrame {
+    pub index: usize,
+    pub image: RgbaImage,
+}
+
+/// A `FrameDecoder` on its own thread. Ask for frames with `request`; they
+/// come back through `try_recv` as they're decoded. The thread ends when the
+/// stream is dropped.
+pub struct FrameStream {
+    path: PathBuf,
+    requests: Sender<Vec<usize>>,
+    frames: Receiver<Result<DecodedFrame, String>>,
+}
+
+impl FrameStream {
+    /// `wake` is called from the worker whenever a frame is ready.
+    pub fn spawn(path: impl Into<PathBuf>, wake: impl Fn() + Send + 'static) -> Self {
+        let path = path.into();
+        let (requests, requests_rx) = mpsc::channel();
+        let (frames_tx, frames) = mpsc::channel();
+        let decoder_path = path.clone();
+        thread::spawn(move || {
+            let decoder = FrameDecoder::new(decoder_path);
+            run_stream(decoder, requests_rx, frames_tx, wake);
+        });
+        Self {
+            path,
+            requests,
+            frames,
+        }
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Queues frames in order of urgency, ahead of anything asked for earlier.
+    pub fn request(&self, indices: Vec<usize>) {
+        if !indices.is_empty() {
+            let _ = self.requests.send(indices);
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<Result<DecodedFrame, String>> {
+        self.frames.try_recv().ok()
+    }
+}
+
+fn run_stream(
+    mut decoder: FrameDecoder,
+    requests: Receiver<Vec<usize>>,
+    frames: Sender<Result<DecodedFrame, String>>,
+    wake: impl Fn(),
+) {
+    let mut queue: VecDeque<usize> = VecDeque::new();
+    let enqueue = |queue: &mut VecDeque<usize>, indices: Vec<usize>| {
+        for index in indices.into_iter().rev() {
+            queue.retain(|&queued| queued != index);
+            queue.push_front(index);
+        }
+    };
+    loop {
+        if queue.is_empty() {
+            match requests.recv() {
+                Ok(indices) => enqueue(&mut queue, indices),
+                Err(_) => return,
+            }
+        }
+        while let Ok(indices) = requests.try_recv() {
+            enqueue(&mut queue, indices);
+        }
+        // Frames past the end, if the file has fewer than its info said,
+        // are never coming.
+        if let Some(count) = decoder.frame_count() {
+            queue.retain(|&queued| queued < count);
+        }
+        let Some(&target) = queue.front() else {
+            continue;
+        };
+
+        // Every queued frame passed on the way to the target is handed over
+        // too, so playing backwards doesn't rewind for each frame.
+        if decoder.position() > target {
+            decoder.rewind();
+        }
+        loop {
+            let (index, image) = match decoder.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => {
+                    queue.retain(|&queued| queued != target);
+                    if frames.send(Err(e)).is_err() {
+                        return;
+                    }
+                    wake();
+                    break;
+                }
+            };
+            if let Some(position) = queue.iter().position(|&queued| queued == index) {
+                queue.remove(position);
+                if frames.send(Ok(DecodedFrame { index, image })).is_err() {
+                    return;
+                }
+                wake();
+            }
+            if index >= target {
+                break;
+            }
+        }
+    }
+}
+
+struct CacheEntry<T> {
+    value: T,
+    bytes: usize,
+    last_used: u64,
+}
+
+/// Frames of every asset, keyed by asset and frame index, evicting the least
+/// recently used once over budget. Instances of the same asset share entries.
+pub struct FrameCache<T> {
+    budget: usize,
+    used: usize,
+    clock: u64,
+    entries: HashMap<(Uuid, usize), CacheEntry<T>>,
+}
+
+impl<T> FrameCache<T> {
+    pub fn new(budget_bytes: usize) -> Self {
+        Self {
+            budget: budget_bytes,
+            used: 0,
+            clock: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn set_budget(&mut self, budget_bytes: usize) {
+        self.budget = budget_bytes;
+        self.evict(0);
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.used
+    }
+
+    pub fn asset_bytes(&self, asset: Uuid) -> usize {
+        self.entries
+            .iter()
+            .filter(|((a, _), _)| *a == asset)
+            .map(|(_, entry)| entry.bytes)
+            .sum()
+    }
+
+    pub fn contains(&self, asset: Uuid, index: usize) -> bool {
+        self.entries.contains_key(&(asset, index))
+    }
+
+    /// Marks the frame as used.
+    pub fn get(&mut self, asset: Uuid, index: usize) -> Option<&T> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(&(asset, index))?;
+        entry.last_used = self.clock;
+        Some(&entry.value)
+    }
+
+    /// Adds a frame, making room for it first. A frame bigger than the whole
+    /// budget is still kept, alone.
+    pub fn insert(&mut self, asset: Uuid, index: usize, value: T, bytes: usize) {
+        self.remove(asset, index);
+        self.evict(bytes);
+        self.clock += 1;
+        self.used += bytes;
+        let entry = CacheEntry {
+            value,
+            bytes,
+            last_used: self.clock,
+        };
+        self.entries.insert((asset, index), entry);
+    }
+
+    fn remove(&mut self, asset: Uuid, index: usize) {
+        if let Some(entry) = self.entries.remove(&(asset, index)) {
+            self.used -= entry.bytes;
+        }
+    }
+
+    pub fn remove_asset(&mut self, asset: Uuid) {
+        let indices: Vec<usize> = self
+            .entries
+            .keys()
+            .filter(|(a, _)| *a == asset)
+            .map(|(_, index)| *index)
+            .collect();
+        for index in indices {
+            self.remove(asset, index);
+        }
+    }
+
+    /// Drops least recently used frames until `incoming` more bytes fit.
+    fn evict(&mut self, incoming: usize) {
+        while self.used + incoming > self.budget {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+            self.remove(oldest.0, oldest.1);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// Named library profiles, each a separate library.json under the data directory
+use crate::character_lib::{self, CharacterLibrary, LIBRARY_FILE};
+use crate::migrations;
+use crate::paths;
+use crate::store::{FsStore, LibraryStore};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The profile that has always existed; its library stays at the top of the
+/// data directory so older installs need no migration.
+pub const DEFAULT_PROFILE: &str = "Default";
+
+#[derive(Debug)]
+pub enum ProfileError {
+    InvalidName(String),
+    AlreadyExists(String),
+    NotFound(String),
+    /// The default profile can't be renamed or deleted.
+    Protected,
+    Io(io::Error),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::InvalidName(name) => {
+                write!(f, "\"{}\" is not a valid profile name", name)
+            }
+            ProfileError::AlreadyExists(name) => {
+                write!(f, "A profile named \"{}\" already exists", name)
+            }
+            ProfileError::NotFound(name) => write!(f, "Profile \"{}\" not found", name),
+            ProfileError::Protected => {
+                write!(
+                    f,
+                    "The {} profile can't be renamed or deleted",
+                    DEFAULT_PROFILE
+                )
+            }
+            ProfileError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl From<io::Error> for ProfileError {
+    fn from(e: io::Error) -> Self {
+        ProfileError::Io(e)
+    }
+}
+
+fn profiles_dir() -> PathBuf {
+    paths::data_dir().join("profiles")
+}
+
+fn profile_dir(name: &str) -> PathBuf {
+    if name == DEFAULT_PROFILE {
+        paths::data_dir()
+    } else {
+        profiles_dir().join(name)
+    }
+}
+
+pub fn exists(name: &str) -> bool {
+    name == DEFAULT_PROFILE || profile_dir(name).is_dir()
+}
+
+/// The default profile first, then the others alphabetically.
+pub fn list() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(profiles_dir())
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|e| e.path().is_dir())
+                .filter_map(|e| e.file_name().to_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort_by_key(|n| n.to_lowercase());
+    names.insert(0, DEFAULT_PROFILE.to_string());
+    names
+}
+
+pub fn store_for(name: &str) -> Arc<dyn LibraryStore> {
+    Arc::new(FsStore::new(profile_dir(name)))
+}
+
+/// Loads a profile's library; an unknown name falls back to the default profile.
+pub fn load(name: &str) -> CharacterLibrary {
+    if name == DEFAULT_PROFILE || !exists(name) {
+        CharacterLibrary::load()
+    } else {
+        CharacterLibrary::load_from(store_for(name))
+    }
+}
+
+fn validate_new_name(name: &str) -> Result<(), ProfileError> {
+    let valid = !name.trim().is_empty()
+        && name.trim() == name
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\', ':']);
+    if !valid {
+        return Err(ProfileError::InvalidName(name.to_string()));
+    }
+    if exists(name) {
+        return Err(ProfileError::AlreadyExists(name.to_string()));
+    }
+    Ok(())
+}
+
+pub fn create(name: &str) -> Result<(), ProfileError> {
+    validate_new_name(name)?;
+    fs::create_dir_all(profile_dir(name))?;
+    Ok(())
+}
+
+/// Copies a profile's library into a new profile. Flush the source first if
+/// it's the one in use.
+pub fn clone_profile(from: &str, to: &str) -> Result<(), ProfileError> {
+    if !exists(from) {
+        return Err(ProfileError::NotFound(from.to_string()));
+    }
+    validate_new_name(to)?;
+    let source = store_for(from);
+    fs::create_dir_all(profile_dir(to))?;
+    if let Some(library) = source.read(LIBRARY_FILE)? {
+        store_for(to).write(LIBRARY_FILE, &library)?;
+    }
+    Ok(())
+}
+
+pub fn rename(from: &str, to: &str) -> Result<(), ProfileError> {
+    if from == DEFAULT_PROFILE {
+        return Err(ProfileError::Protected);
+    }
+    if !exists(from) {
+        return Err(ProfileError::NotFound(from.to_string()));
+    }
+    validate_new_name(to)?;
+    fs::rename(profile_dir(from), profile_dir(to))?;
+    Ok(())
+}
+
+pub fn delete(name: &str) -> Result<(), ProfileError> {
+    if name == DEFAULT_PROFILE {
+        return Err(ProfileError::Protected);
+    }
+    if !exists(name) {
+        return Err(ProfileError::NotFound(name.to_string()));
+    }
+    fs::remove_dir_all(profile_dir(name))?;
+    Ok(())
+}
+
+/// Paths of a profile's characters, and the originals of an optimization it
+/// hasn't confirmed yet, as stored on disk. Read without loading the library
+/// so a damaged file isn't quarantined as a side effect.
+fn stored_paths(name: &str) -> Option<Vec<PathBuf>> {
+    let content = match store_for(name).read(LIBRARY_FILE) {
+        Ok(Some(content)) => content,
+        Ok(None) => return Some(Vec::new()),
+        Err(_) => return None,
+    };
+    let value: Value = serde_json::from_slice(&content).ok()?;
+    let value = migrations::migrate_library(value).ok()?;
+    let root = character_lib::media_root_dir(value["media_root"].as_str());
+    let originals = value["pending_optimization"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e["original_path"].as_str());
+    let paths = value["characters"]
+        .as_array()?
+        .iter()
+        .filter_map(|c| c["path"].as_str())
+        .chain(originals)
+        .map(|path| PathBuf::from(character_lib::resolve_media_path(&root, path)))
+        .collect();
+    Some(paths)
+}
+
+/// Every file any profile points at or still waits on, or `None` if a profile
+/// couldn't be read, in which case nothing should be deleted. `current` is the
+/// library in use, which may have changes that aren't on disk yet.
+pub fn referenced_paths(
+    current_name: &str,
+    current: &CharacterLibrary,
+) -> Option<HashSet<PathBuf>> {
+    let originals = current
+        .pending_optimization
+        .iter()
+        .map(|e| &e.original_path);
+    let mut paths: HashSet<PathBuf> = current
+        .characters
+        .iter()
+        .map(|c| &c.path)
+        .chain(originals)
+        .map(PathBuf::from)
+        .collect();
+    for name in list().iter().filter(|n| *n != current_name) {
+        paths.extend(stored_paths(name)?);
+    }
+    Some(paths)
+}
@@ -0,0 +1,38 @@
+// Redraw pacing: how often character windows may be redrawn, and how often
+// they actually are
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_FPS_CAP: u32 = 60;
+/// The cap in power saver mode, whatever the regular cap is.
+pub const POWER_SAVER_FPS_CAP: u32 = 15;
+
+/// When to redraw for a frame due in `due_in`, but no sooner than `fps_cap`
+/// allows. Animations keep their timing; frames that come too fast are skipped.
+pub fn paced(due_in: Duration, fps_cap: Option<u32>) -> Duration {
+    match fps_cap {
+        Some(cap) if cap > 0 => due_in.max(Duration::from_secs(1) / cap),
+        _ => due_in,
+    }
+}
+
+/// Counts redraws over the last second.
+#[derive(Debug, Clone, Default)]
+pub struct RedrawRate {
+    times: VecDeque<Instant>,
+}
+
+impl RedrawRate {
+    pub fn record(&mut self, now: Instant) {
+        self.times.push_back(now);
+        while let Some(&oldest) = self.times.front()
+            && now.duration_since(oldest) > Duration::from_secs(1)
+        {
+            self.times.pop_front();
+        }
+    }
+
+    pub fn per_second(&self) -> usize {
+        self.times.len()
+    }
+}
@@ -0,0 +1,155 @@
+// Finding moved media files again, by file name and content hash
+use crate::cache;
+use crate::character_lib::Character;
+use crate::jobs::Progress;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Extensions worth hashing while searching; anything else can only match by name.
+const MEDIA_EXTENSIONS: &[&str] = &["png", "apng", "webp", "gif"];
+
+/// A character whose file is gone, as handed to the search job.
+#[derive(Debug, Clone)]
+pub struct MissingFile {
+    pub id: Uuid,
+    pub name: String,
+    pub path: String,
+    pub content_hash: Option<String>,
+}
+
+impl MissingFile {
+    pub fn from_character(character: &Character) -> Self {
+        Self {
+            id: character.id,
+            name: character.name.clone(),
+            path: character.path.clone(),
+            content_hash: character.content_hash.clone(),
+        }
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        Path::new(&self.path).file_name().and_then(|n| n.to_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchKind {
+    /// Same file name, but the content couldn't be checked or differs.
+    Name,
+    /// Same content under another name.
+    Hash,
+    NameAndHash,
+}
+
+#[derive(Debug, Clone)]
+pub struct RelinkMatch {
+    pub id: Uuid,
+    pub new_path: String,
+    pub kind: MatchKind,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RelinkReport {
+    pub matches: Vec<RelinkMatch>,
+    /// Names of characters nothing was found for.
+    pub not_found: Vec<String>,
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>, progress: &Progress) {
+    if progress.is_cancelled() {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        // Symlinked directories are skipped so a loop can't keep the search going forever.
+        if file_type.is_dir() {
+            collect_files(&entry.path(), files, progress);
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+}
+
+/// Searches `folder` and its subfolders for each missing file. A file with
+/// the recorded content hash wins, preferably one with the same name; a file
+/// with only the same name is used when nothing better turns up. Runs as a
+/// background job.
+pub fn search(folder: &Path, missing: &[MissingFile], progress: &Progress) -> RelinkReport {
+    progress.set_stage("Listing files");
+    let mut files = Vec::new();
+    collect_files(folder, &mut files, progress);
+
+    let wanted_hashes: HashMap<&str, usize> = missing
+        .iter()
+        .enumerate()
+        .filter_map(|(i, m)| m.content_hash.as_deref().map(|h| (h, i)))
+        .collect();
+    let mut best: Vec<Option<(MatchKind, PathBuf)>> = vec![None; missing.len()];
+
+    progress.set_stage("Checking files");
+    progress.set_total(files.len() as u64);
+    for (done, file) in files.into_iter().enumerate() {
+        if progress.is_cancelled() {
+            break;
+        }
+        progress.set_done(done as u64);
+
+        let file_name = file.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let extension = file
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .unwrap_or_default();
+        let named: Vec<usize> = (0..missing.len())
+            .filter(|&i| missing[i].file_name() == Some(file_name))
+            .collect();
+        // Hashing is the slow part, so only files that could match are read.
+        let hash = if !named.is_empty()
+            || (!wanted_hashes.is_empty() && MEDIA_EXTENSIONS.contains(&extension.as_str()))
+        {
+            cache::content_hash(&file).ok()
+        } else {
+            None
+        };
+
+        let by_hash = hash.as_deref().and_then(|h| wanted_hashes.get(h).copied());
+        let mut offer = |i: usize, kind: MatchKind| {
+            if best[i].as_ref().is_none_or(|(current, _)| kind > *current) {
+                best[i] = Some((kind, file.clone()));
+            }
+        };
+        for &i in &named {
+            let kind = if by_hash == Some(i) {
+                MatchKind::NameAndHash
+            } else {
+                MatchKind::Name
+            };
+            offer(i, kind);
+        }
+        if let Some(i) = by_hash
+            && !named.contains(&i)
+        {
+            offer(i, MatchKind::Hash);
+        }
+    }
+
+    let mut report = RelinkReport::default();
+    for (missing, found) in missing.iter().zip(best) {
+        match found {
+            Some((kind, path)) => report.matches.push(RelinkMatch {
+                id: missing.id,
+                new_path: path.to_string_lossy().to_string(),
+                kind,
+            }),
+            None => report.not_found.push(missing.name.clone()),
+        }
+    }
+    report
+}
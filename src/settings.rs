@@ -1,6 +1,12 @@
+use crate::convert::ConvertOptions;
+use crate::paths;
+use crate::persist::SaveTracker;
+use crate::profiles;
+use crate::redraw;
+use crate::store::{self, FsStore, LibraryStore};
 use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppSettings {
@@ -9,6 +15,52 @@ pub struct AppSettings {
     pub image_scale: f32,
     pub window_width: f32,
     pub window_height: f32,
+    /// Defaults for every import; can be overridden per file.
+    #[serde(default)]
+    pub conversion: ConvertOptions,
+    /// Show the conversion options before importing any file, not just videos.
+    #[serde(default)]
+    pub ask_import_options: bool,
+    /// Library profile to open on startup; see `profiles`.
+    #[serde(default = "default_profile")]
+    pub active_profile: String,
+    /// Memory for decoded animation frames, shared by every character on screen.
+    #[serde(default = "default_frame_cache_mb")]
+    pub frame_cache_mb: u32,
+    /// Most redraws per second of a character window; `None` for no limit.
+    #[serde(default = "default_fps_cap")]
+    pub fps_cap: Option<u32>,
+    /// Caps redraws at `redraw::POWER_SAVER_FPS_CAP`.
+    #[serde(default)]
+    pub power_saver: bool,
+    /// Debug overlay with how often each window is actually redrawn.
+    #[serde(default)]
+    pub show_redraw_rate: bool,
+    /// Set when the file on disk couldn't be loaded, for the UI to show.
+    #[serde(skip)]
+    pub load_warning: Option<String>,
+    #[serde(skip)]
+    tracker: SaveTracker,
+    #[serde(skip, default = "default_store")]
+    store: Arc<dyn LibraryStore>,
+}
+
+const SETTINGS_FILE: &str = "settings.json";
+
+fn default_store() -> Arc<dyn LibraryStore> {
+    Arc::new(FsStore::new(paths::config_dir()))
+}
+
+fn default_profile() -> String {
+    profiles::DEFAULT_PROFILE.to_string()
+}
+
+fn default_frame_cache_mb() -> u32 {
+    256
+}
+
+fn default_fps_cap() -> Option<u32> {
+    Some(redraw::DEFAULT_FPS_CAP)
 }
 
 impl Default for AppSettings {
@@ -19,40 +71,104 @@ impl Default for AppSettings {
             image_scale: 1.0,
             window_width: 400.0,
             window_height: 520.0,
+            conversion: ConvertOptions::default(),
+            ask_import_options: false,
+            active_profile: default_profile(),
+            frame_cache_mb: default_frame_cache_mb(),
+            fps_cap: default_fps_cap(),
+            power_saver: false,
+            show_redraw_rate: false,
+            load_warning: None,
+            tracker: SaveTracker::default(),
+            store: default_store(),
         }
     }
 }
 
 impl AppSettings {
-    pub fn load() -> Self {
-        let path = Self::get_settings_path();
-        if path.exists() {
-            match fs::read_to_string(&path) {
-                Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
-                Err(_) => Self::default(),
-            }
+    /// The redraw cap in effect, taking power saver mode into account.
+    pub fn effective_fps_cap(&self) -> Option<u32> {
+        if self.power_saver {
+            let cap = self.fps_cap.unwrap_or(u32::MAX);
+            Some(cap.min(redraw::POWER_SAVER_FPS_CAP))
         } else {
-            Self::default()
+            self.fps_cap
         }
     }
 
-    pub fn save(&self) {
-        let path = Self::get_settings_path();
-        if let Some(parent) = path.parent() {
-            let _ = fs::create_dir_all(parent);
+    pub fn load() -> Self {
+        Self::load_from(default_store())
+    }
+
+    /// Loads settings kept in `store`, falling back to the defaults when there
+    /// are none. A file that can't be read or parsed is copied aside first,
+    /// since the next save would overwrite it.
+    pub fn load_from(store: Arc<dyn LibraryStore>) -> Self {
+        let mut settings: Self = match store.read(SETTINGS_FILE) {
+            Ok(Some(content)) => match serde_json::from_slice(&content) {
+                Ok(settings) => settings,
+                Err(e) => Self::quarantined(&*store, &format!("invalid JSON: {}", e)),
+            },
+            Ok(None) => Self::default(),
+            Err(e) => Self::quarantined(&*store, &format!("could not read it: {}", e)),
+        };
+        settings.store = store;
+        settings
+    }
+
+    /// Defaults, after keeping a copy of the settings file that failed to load.
+    fn quarantined(store: &dyn LibraryStore, reason: &str) -> Self {
+        Self {
+            load_warning: Some(store::quarantine(store, SETTINGS_FILE, "settings", reason)),
+            ..Self::default()
         }
-        let content = serde_json::to_string_pretty(self).unwrap_or_default();
-        let _ = fs::write(&path, content);
     }
 
-    fn get_settings_path() -> PathBuf {
-        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-        if cfg!(target_os = "macos") {
-            home.join("Library/Application Support/AnimaMac/settings.json")
-        } else if cfg!(windows) {
-            home.join("AppData/Roaming/AnimaMac/settings.json")
+    pub fn save(&self) -> std::io::Result<()> {
+        self.write().map(|_| ())
+    }
+
+    fn write(&self) -> std::io::Result<usize> {
+        let content = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        self.store.write(SETTINGS_FILE, content.as_bytes())?;
+        Ok(content.len())
+    }
+
+    /// Records a change; it's written out by the next `flush_if_due` or `flush`.
+    pub fn mark_dirty(&mut self) {
+        self.tracker.mark_dirty();
+    }
+
+    /// Writes any pending changes.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if !self.tracker.is_dirty() {
+            return Ok(());
+        }
+        let started = Instant::now();
+        match self.write() {
+            Ok(bytes) => {
+                self.tracker
+                    .record_write("settings", bytes, started.elapsed());
+                Ok(())
+            }
+            Err(e) => {
+                self.tracker.record_failure();
+                Err(e)
+            }
+        }
+    }
+
+    /// Flushes once changes have settled; call this every frame.
+    pub fn flush_if_due(&mut self) -> std::io::Result<()> {
+        if self.tracker.is_due() {
+            self.flush()
         } else {
-            home.join(".config/animatux/settings.json")
+            Ok(())
         }
     }
+
+    /// How long until pending changes get flushed, `None` when there are none.
+    pub fn flush_due_in(&self) -> Option<Duration> {
+        self.tracker.due_in()
+    }
 }
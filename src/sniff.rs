@@ -0,0 +1,249 @@
+// File format detection from magic bytes rather than file names
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek};
+use std::path::Path;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Major brands of `ftyp` files that hold video. The same container also
+/// carries still images (AVIF, HEIC) and audio, which aren't characters.
+const MP4_BRANDS: &[&[u8; 4]] = &[
+    b"isom", b"iso2", b"iso3", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ",
+    b"M4VH", b"M4VP", b"mmp4", b"dash", b"3gp4", b"3gp5", b"3gp6", b"3g2a", b"f4v ", b"MSNV",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MediaFormat {
+    Png,
+    Apng,
+    Gif,
+    Webp,
+    Mp4,
+    Mov,
+    Webm,
+}
+
+impl MediaFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MediaFormat::Png => "png",
+            MediaFormat::Apng => "apng",
+            MediaFormat::Gif => "gif",
+            MediaFormat::Webp => "webp",
+            MediaFormat::Mp4 => "mp4",
+            MediaFormat::Mov => "mov",
+            MediaFormat::Webm => "webm",
+        }
+    }
+
+    pub fn is_video(&self) -> bool {
+        matches!(
+            self,
+            MediaFormat::Mp4 | MediaFormat::Mov | MediaFormat::Webm
+        )
+    }
+
+    /// Whether a file with this extension would be recognised as this format by name alone.
+    pub fn matches_extension(&self, path: &Path) -> bool {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase())
+            .unwrap_or_default();
+        match self {
+            // APNGs are very often shipped with a plain .png extension.
+            MediaFormat::Apng => ext == "apng" || ext == "png",
+            MediaFormat::Mp4 => ext == "mp4" || ext == "m4v",
+            // ffmpeg reads the container itself, so any of the QuickTime family will do.
+            MediaFormat::Mov => ext == "mov" || ext == "mp4",
+            MediaFormat::Webm => ext == "webm" || ext == "mkv",
+            _ => ext == self.extension(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sniffed {
+    pub format: MediaFormat,
+    pub animated: bool,
+}
+
+/// Enough to identify every format; PNG and GIF then need more of the file
+/// to find out whether they are animated.
+const HEAD_LEN: u64 = 64;
+
+pub fn sniff_file(path: &Path) -> io::Result<Option<Sniffed>> {
+    let mut file = File::open(path)?;
+    let mut head = vec![];
+    (&mut file).take(HEAD_LEN).read_to_end(&mut head)?;
+    let sniffed = sniff_bytes(&head);
+    // Walk the block headers from the top, skipping image data rather than
+    // reading whole files.
+    match sniffed.map(|s| s.format) {
+        Some(MediaFormat::Png | MediaFormat::Apng) => {
+            file.rewind()?;
+            let animated = png_has_actl(BufReader::new(file));
+            Ok(Some(Sniffed {
+                format: if animated {
+                    MediaFormat::Apng
+                } else {
+                    MediaFormat::Png
+                },
+                animated,
+            }))
+        }
+        Some(MediaFormat::Gif) => {
+            file.rewind()?;
+            Ok(Some(Sniffed {
+                format: MediaFormat::Gif,
+                animated: gif_image_count(BufReader::new(file)) > 1,
+            }))
+        }
+        _ => Ok(sniffed),
+    }
+}
+
+pub fn sniff_bytes(bytes: &[u8]) -> Option<Sniffed> {
+    if bytes.starts_with(PNG_SIGNATURE) {
+        let animated = png_has_actl(bytes);
+        return Some(Sniffed {
+            format: if animated {
+                MediaFormat::Apng
+            } else {
+                MediaFormat::Png
+            },
+            animated,
+        });
+    }
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        return Some(Sniffed {
+            format: MediaFormat::Gif,
+            animated: gif_image_count(bytes) > 1,
+        });
+    }
+    if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some(Sniffed {
+            format: MediaFormat::Webp,
+            animated: webp_is_animated(bytes),
+        });
+    }
+    // Matroska/WebM EBML header.
+    if bytes.starts_with(b"\x1a\x45\xdf\xa3") {
+        return Some(Sniffed {
+            format: MediaFormat::Webm,
+            animated: true,
+        });
+    }
+    if bytes.len() >= 12 {
+        let format = match (&bytes[4..8], &bytes[8..12]) {
+            (b"ftyp", b"qt  ") => Some(MediaFormat::Mov),
+            (b"ftyp", brand) if MP4_BRANDS.iter().any(|b| b[..] == *brand) => {
+                Some(MediaFormat::Mp4)
+            }
+            // Older QuickTime files start straight with one of these atoms.
+            (b"moov" | b"mdat" | b"wide" | b"free" | b"skip", _) => Some(MediaFormat::Mov),
+            _ => None,
+        };
+        if let Some(format) = format {
+            return Some(Sniffed {
+                format,
+                animated: true,
+            });
+        }
+    }
+    None
+}
+
+/// An APNG is a PNG with an acTL chunk somewhere before the first IDAT.
+fn png_has_actl(reader: impl BufRead) -> bool {
+    find_actl(reader).unwrap_or(false)
+}
+
+/// Reads chunk headers only, skipping their data.
+fn find_actl(mut reader: impl BufRead) -> io::Result<bool> {
+    skip(&mut reader, PNG_SIGNATURE.len() as u64)?;
+    loop {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        match &header[4..8] {
+            b"acTL" => return Ok(true),
+            b"IDAT" | b"IEND" => return Ok(false),
+            _ => {}
+        }
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        // Chunk data and CRC.
+        skip(&mut reader, len as u64 + 4)?;
+    }
+}
+
+/// Counts image descriptors, stopping once the GIF is known to be animated.
+fn gif_image_count(mut reader: impl BufRead) -> usize {
+    let mut images = 0;
+    // A truncated file has as many images as were found before it ends.
+    let _ = count_gif_images(&mut reader, &mut images);
+    images
+}
+
+fn count_gif_images(reader: &mut impl BufRead, images: &mut usize) -> io::Result<()> {
+    // Header (6) + logical screen descriptor (7).
+    let mut header = [0u8; 13];
+    reader.read_exact(&mut header)?;
+    skip_colour_table(reader, header[10])?;
+
+    while *images < 2 {
+        match read_u8(reader)? {
+            // Extension: introducer, label, then data sub-blocks.
+            0x21 => {
+                read_u8(reader)?;
+                skip_sub_blocks(reader)?;
+            }
+            // Image descriptor, optional local colour table, LZW code size, data sub-blocks.
+            0x2C => {
+                *images += 1;
+                let mut descriptor = [0u8; 9];
+                reader.read_exact(&mut descriptor)?;
+                skip_colour_table(reader, descriptor[8])?;
+                read_u8(reader)?;
+                skip_sub_blocks(reader)?;
+            }
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+fn skip_colour_table(reader: &mut impl BufRead, flags: u8) -> io::Result<()> {
+    if flags & 0x80 != 0 {
+        skip(reader, 3 << ((flags & 0x07) + 1))?;
+    }
+    Ok(())
+}
+
+fn skip_sub_blocks(reader: &mut impl BufRead) -> io::Result<()> {
+    loop {
+        let size = read_u8(reader)?;
+        if size == 0 {
+            return Ok(());
+        }
+        skip(reader, size as u64)?;
+    }
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn skip(reader: &mut impl BufRead, len: u64) -> io::Result<()> {
+    if io::copy(&mut reader.take(len), &mut io::sink())? < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Extended WebP files flag animation in the VP8X header.
+fn webp_is_animated(bytes: &[u8]) -> bool {
+    bytes.len() >= 21 && &bytes[12..16] == b"VP8X" && bytes[20] & 0x02 != 0
+}
@@ -1,6 +1,10 @@
 // Optional feature to support steamcmd for workshop content
+use crate::convert::find_command;
+use crate::jobs::{self, Progress};
 use crate::logging::log_to_file;
-use std::{env::home_dir, path::Path, process::Command};
+use crate::sniff::sniff_file;
+use crate::paths;
+use std::{path::Path, process::Command};
 
 #[derive(Debug, Clone)]
 pub struct DownloadResult {
@@ -9,13 +13,7 @@ pub struct DownloadResult {
 }
 
 pub fn get_ws() -> String {
-    let home = match home_dir() {
-        Some(h) => h,
-        None => {
-            log_to_file("steamlib: home_dir() returned None; defaulting to current dir");
-            return ".".to_string();
-        }
-    };
+    let home = paths::home_dir();
     return if cfg!(windows) {
         format!(
             "C:\\Program Files (x86)\\Steam\\steamapps\\workshop\\content\\{}",
@@ -28,39 +26,16 @@ pub fn get_ws() -> String {
             include_str!("../assets/appid")
         )
     } else {
+        // Steam follows XDG_DATA_HOME like everything else on Linux.
+        let data = dirs::data_dir().unwrap_or_else(|| home.join(".local/share"));
         format!(
-            "{}/.local/share/Steam/steamapps/workshop/content/{}",
-            home.to_str().unwrap_or("."),
+            "{}/Steam/steamapps/workshop/content/{}",
+            data.to_str().unwrap_or("."),
             include_str!("../assets/appid")
         )
     };
 }
 
-fn find_command(name: &str) -> Option<String> {
-    let paths = [
-        format!("/opt/homebrew/bin/{}", name),
-        format!("/usr/local/bin/{}", name),
-        format!("/usr/bin/{}", name),
-    ];
-
-    for path in &paths {
-        if std::path::Path::new(path).exists() {
-            return Some(path.clone());
-        }
-    }
-
-    if let Ok(output) = std::process::Command::new("which").arg(name).output() {
-        if output.status.success() {
-            let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if !path.is_empty() {
-                return Some(path);
-            }
-        }
-    }
-
-    None
-}
-
 pub fn extract_workshop_id(input: &str) -> String {
     let input = input.trim();
 
@@ -88,94 +63,18 @@ pub fn extract_workshop_id(input: &str) -> String {
     input.to_string()
 }
 
-fn is_valid_media_file(filename: &str) -> bool {
-    let filename = filename.to_lowercase();
+fn is_valid_media_file(path: &Path) -> bool {
+    let filename = match path.file_name().and_then(|f| f.to_str()) {
+        Some(f) => f.to_lowercase(),
+        None => return false,
+    };
     if filename.starts_with('.') {
         return false;
     }
     if filename == "ds_store" {
         return false;
     }
-    filename.ends_with(".apng")
-        || filename.ends_with(".png")
-        || filename.ends_with(".webp")
-        || filename.ends_with(".gif")
-}
-
-fn convert_apng_to_webp_internal(input_path: &str, output_path: &str) -> Result<(), String> {
-    use std::fs;
-    use std::process::Command;
-    use tempfile::TempDir;
-
-    println!("Converting APNG to animated WebP...");
-
-    let temp_dir = TempDir::new().map_err(|e| e.to_string())?;
-    let frames_dir = temp_dir.path();
-
-    let frames_pattern = frames_dir.join("f%03d.png");
-
-    println!("Extracting frames with ffmpeg...");
-    let ffmpeg_output = Command::new("ffmpeg")
-        .arg("-y")
-        .arg("-i")
-        .arg(input_path)
-        .arg(frames_pattern.to_str().unwrap())
-        .output()
-        .map_err(|e| e.to_string())?;
-
-    if !ffmpeg_output.status.success() {
-        return Err(format!(
-            "ffmpeg failed: {}",
-            String::from_utf8_lossy(&ffmpeg_output.stderr)
-        ));
-    }
-
-    let mut png_files: Vec<String> = fs::read_dir(frames_dir)
-        .map_err(|e| e.to_string())?
-        .filter_map(|e| e.ok())
-        .map(|e| e.path().to_string_lossy().to_string())
-        .filter(|s| s.ends_with(".png"))
-        .collect();
-
-    png_files.sort();
-
-    println!("Found {} PNG files", png_files.len());
-
-    if png_files.is_empty() {
-        return Err("No PNG files created by ffmpeg".to_string());
-    }
-
-    println!("Creating animated WebP with img2webp...");
-
-    let mut cmd = Command::new("img2webp");
-    for f in &png_files {
-        cmd.arg(f);
-    }
-    cmd.arg("-o").arg(output_path);
-
-    let img2webp_output = cmd.output().map_err(|e| e.to_string())?;
-
-    if !img2webp_output.status.success() {
-        return Err(format!(
-            "img2webp failed: {}",
-            String::from_utf8_lossy(&img2webp_output.stderr)
-        ));
-    }
-
-    println!("Animated WebP saved successfully!");
-    Ok(())
-}
-
-fn convert_apng_to_webp(input_path: &str, output_path: &str) -> bool {
-    println!("Converting APNG to WebP: {} -> {}", input_path, output_path);
-
-    match convert_apng_to_webp_internal(input_path, output_path) {
-        Ok(_) => true,
-        Err(e) => {
-            println!("Conversion failed: {}", e);
-            false
-        }
-    }
+    matches!(sniff_file(path), Ok(Some(_)))
 }
 
 pub fn list_ws(ws: &String) -> Vec<String> {
@@ -208,7 +107,7 @@ pub fn list_ws(ws: &String) -> Vec<String> {
             let fname = file.file_name();
             let fname_str = fname.to_str().unwrap();
 
-            if !is_valid_media_file(fname_str) {
+            if !is_valid_media_file(&file.path()) {
                 continue;
             }
 
@@ -225,7 +124,10 @@ pub fn list_ws(ws: &String) -> Vec<String> {
     return ids;
 }
 
-pub fn workshop_dl(id: &String, ws: &String) -> Option<DownloadResult> {
+/// Downloads a workshop item with steamcmd, reporting its output lines as the
+/// current stage. Meant to run as a background job; returns `None` on failure
+/// or when cancelled.
+pub fn workshop_dl(id: &String, ws: &String, progress: &Progress) -> Option<DownloadResult> {
     let workshop_id = extract_workshop_id(id);
 
     log_to_file(&format!(
@@ -242,18 +144,32 @@ pub fn workshop_dl(id: &String, ws: &String) -> Option<DownloadResult> {
     };
 
     log_to_file(&format!("steamlib: using steamcmd at {}", steamcmd_path));
-
-    let output = Command::new(&steamcmd_path)
-        .arg("+login")
-        .arg("anonymous")
-        .arg("+workshop_download_item")
-        .arg(include_str!("../assets/appid"))
-        .arg(&workshop_id)
-        .arg("+quit")
-        .output();
+    progress.set_stage("Starting steamcmd");
+
+    let stage = progress.clone();
+    let output = jobs::run_command(
+        Command::new(&steamcmd_path)
+            .arg("+login")
+            .arg("anonymous")
+            .arg("+workshop_download_item")
+            .arg(include_str!("../assets/appid"))
+            .arg(&workshop_id)
+            .arg("+quit"),
+        progress,
+        move |line| {
+            let line = line.trim();
+            if !line.is_empty() {
+                stage.set_stage(line);
+            }
+        },
+    );
 
     let output = match output {
-        Ok(o) => o,
+        Ok(Some(o)) => o,
+        Ok(None) => {
+            log_to_file(&format!("steamlib: download of {} cancelled", workshop_id));
+            return None;
+        }
         Err(e) => {
             log_to_file(&format!("steamlib: failed to execute steamcmd: {}", e));
             return None;
@@ -263,12 +179,10 @@ pub fn workshop_dl(id: &String, ws: &String) -> Option<DownloadResult> {
     let path = format!("{}/{}", ws, workshop_id);
 
     if !output.stdout.is_empty() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        log_to_file(&format!("steamlib: steamcmd stdout:\n{}", stdout));
+        log_to_file(&format!("steamlib: steamcmd stdout:\n{}", output.stdout));
     }
     if !output.stderr.is_empty() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        log_to_file(&format!("steamlib: steamcmd stderr:\n{}", stderr));
+        log_to_file(&format!("steamlib: steamcmd stderr:\n{}", output.stderr));
     }
 
     if !Path::new(&path).exists() {
@@ -276,6 +190,7 @@ pub fn workshop_dl(id: &String, ws: &String) -> Option<DownloadResult> {
         return None;
     }
 
+    progress.set_stage("Listing downloaded files");
     process_downloaded_files(&path)
 }
 
@@ -296,23 +211,12 @@ fn process_downloaded_files(path: &str) -> Option<DownloadResult> {
     for img in files.flatten() {
         let fname = img.file_name().to_str().unwrap().to_owned();
 
-        if !is_valid_media_file(&fname) {
+        if !is_valid_media_file(&img.path()) {
             continue;
         }
 
-        let full_path = format!("{}/{}", path, fname);
-        let fname_lower = fname.to_lowercase();
-
-        if fname_lower.ends_with(".apng") {
-            let webp_name = fname.trim_end_matches(".apng").to_string() + ".webp";
-            let webp_path = format!("{}/{}", path, webp_name);
-
-            if convert_apng_to_webp(&full_path, &webp_path) {
-                downloaded_files.push(webp_name);
-            }
-        } else {
-            downloaded_files.push(fname);
-        }
+        // APNGs are converted into the app cache when they are added to the library.
+        downloaded_files.push(fname);
     }
 
     if downloaded_files.is_empty() {
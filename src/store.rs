@@ -0,0 +1,205 @@
+// Where the library and settings documents live: on disk, or in memory for tests
+use crate::logging::log_to_file;
+use crate::persist;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Storage for the JSON documents the app keeps (`library.json`, `settings.json`).
+pub trait LibraryStore: fmt::Debug + Send + Sync {
+    /// Returns `None` when the document doesn't exist yet.
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Replaces the document. The previous version becomes backup 1 if the
+    /// newest backup is older than `persist::BACKUP_INTERVAL`.
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()>;
+
+    /// Copies a document aside under another name, e.g. to quarantine it.
+    fn copy(&self, from: &str, to: &str) -> io::Result<()>;
+
+    /// Existing backups of the document, newest first.
+    fn backups(&self, name: &str) -> Vec<(usize, SystemTime)>;
+
+    /// Puts backup `n` back in place; the current version becomes backup 1.
+    fn restore_backup(&self, name: &str, n: usize) -> io::Result<()>;
+
+    /// Where the document lives, for messages shown to the user.
+    fn describe(&self, name: &str) -> String;
+}
+
+/// Copies a document that failed to load aside as `<name>.corrupt-<unix secs>`,
+/// so the next save can't destroy it, and logs it. Returns the warning for the
+/// UI; `what` is how the document is called there.
+pub fn quarantine(store: &dyn LibraryStore, name: &str, what: &str, reason: &str) -> String {
+    let stamp = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let copy = format!("{}.corrupt-{}", name, stamp);
+    let kept = store.copy(name, &copy).is_ok();
+    log_to_file(&format!(
+        "{}: failed to load {} ({}); copy kept: {}",
+        what,
+        store.describe(name),
+        reason,
+        if kept {
+            store.describe(&copy)
+        } else {
+            "no".to_string()
+        }
+    ));
+
+    if kept {
+        format!(
+            "Could not load the {} ({}). The old file was kept as {}",
+            what,
+            reason,
+            store.describe(&copy)
+        )
+    } else {
+        format!("Could not load the {} ({})", what, reason)
+    }
+}
+
+/// Documents as files in a directory, written atomically with rotating backups.
+#[derive(Debug, Clone)]
+pub struct FsStore {
+    root: PathBuf,
+}
+
+impl FsStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+}
+
+impl LibraryStore for FsStore {
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(name)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        persist::write_atomic(&self.path(name), contents)
+    }
+
+    fn copy(&self, from: &str, to: &str) -> io::Result<()> {
+        fs::copy(self.path(from), self.path(to)).map(|_| ())
+    }
+
+    fn backups(&self, name: &str) -> Vec<(usize, SystemTime)> {
+        persist::backups(&self.path(name))
+    }
+
+    fn restore_backup(&self, name: &str, n: usize) -> io::Result<()> {
+        persist::restore_backup(&self.path(name), n)
+    }
+
+    fn describe(&self, name: &str) -> String {
+        self.path(name).display().to_string()
+    }
+}
+
+/// Earlier contents of a document and when they were replaced, newest first.
+type History = Vec<(Vec<u8>, SystemTime)>;
+
+/// Documents kept in memory, for tests and throwaway sessions.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    documents: Mutex<HashMap<String, Vec<u8>>>,
+    history: Mutex<HashMap<String, History>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Same rules as `persist::write_atomic`, so tests see the backups the app would.
+    fn write_with_backup(&self, name: &str, contents: &[u8], force_backup: bool) {
+        let previous = self
+            .documents
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), contents.to_vec());
+        let Some(previous) = previous else {
+            return;
+        };
+        let mut history = self.history.lock().unwrap();
+        let versions = history.entry(name.to_string()).or_default();
+        let due = versions.first().is_none_or(|(_, taken)| {
+            taken
+                .elapsed()
+                .ok()
+                .is_none_or(|age| age >= persist::BACKUP_INTERVAL)
+        });
+        if force_backup || due {
+            versions.insert(0, (previous, SystemTime::now()));
+            versions.truncate(persist::BACKUP_COUNT);
+        }
+    }
+}
+
+impl LibraryStore for MemoryStore {
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.documents.lock().unwrap().get(name).cloned())
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        self.write_with_backup(name, contents, false);
+        Ok(())
+    }
+
+    fn copy(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut documents = self.documents.lock().unwrap();
+        let contents = documents
+            .get(from)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, from.to_string()))?;
+        documents.insert(to.to_string(), contents);
+        Ok(())
+    }
+
+    fn backups(&self, name: &str) -> Vec<(usize, SystemTime)> {
+        self.history
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|versions| {
+                versions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (_, time))| (i + 1, *time))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn restore_backup(&self, name: &str, n: usize) -> io::Result<()> {
+        let contents = self
+            .history
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(|versions| versions.get(n.wrapping_sub(1)))
+            .map(|(contents, _)| contents.clone())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("backup {}", n)))?;
+        self.write_with_backup(name, &contents, true);
+        Ok(())
+    }
+
+    fn describe(&self, name: &str) -> String {
+        format!("memory:{}", name)
+    }
+}
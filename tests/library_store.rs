@@ -0,0 +1,394 @@
+use animamac::character_lib::{CharacterLibrary, OptimizeReport, OptimizedEntry};
+use animamac::jobs::Progress;
+use animamac::persist;
+use animamac::playback::Speed;
+use animamac::relink::{self, MatchKind, MissingFile};
+use animamac::settings::AppSettings;
+use animamac::store::{FsStore, LibraryStore, MemoryStore};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::TempDir;
+use uuid::Uuid;
+
+fn write_png(dir: &Path, name: &str, width: u32, height: u32) -> String {
+    let path = dir.join(name);
+    image::RgbaImage::new(width, height).save(&path).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+/// The id of a character's first instance.
+fn first_instance(library: &CharacterLibrary, index: usize) -> Uuid {
+    library.characters[index].instances[0].id
+}
+
+/// Adds two characters, changes them, and checks the changes survive a reload.
+fn round_trip(store: Arc<dyn LibraryStore>, media: &Path) {
+    let first = write_png(media, "first.png", 8, 4);
+    let second = write_png(media, "second.png", 4, 4);
+
+    let mut library = CharacterLibrary::load_from(store.clone());
+    assert!(library.characters.is_empty());
+    let a = library.add_character(&first).unwrap();
+    let b = library.add_named_character(&second, "Second").unwrap();
+    let (a, b) = (first_instance(&library, a), first_instance(&library, b));
+    library.set_enabled(a, true);
+    library.update_position(a, [120.0, 64.0]);
+    library.update_settings(b, Speed::Multiplier(2.0), 1.5);
+    library.flush();
+    assert!(library.save_error.is_none());
+
+    let reloaded = CharacterLibrary::load_from(store.clone());
+    assert!(reloaded.load_warning.is_none());
+    assert_eq!(reloaded.characters.len(), 2);
+    let first_char = &reloaded.characters[0];
+    assert_eq!(first_char.name, "first");
+    assert_eq!(first_char.path, first);
+    assert!(first_char.instances[0].enabled);
+    assert_eq!(first_char.instances[0].window_pos, Some([120.0, 64.0]));
+    assert_eq!(first_char.media_info.as_ref().unwrap().width, 8);
+    let second_char = &reloaded.characters[1];
+    assert_eq!(second_char.name, "Second");
+    assert!(!second_char.instances[0].enabled);
+    assert_eq!(second_char.instances[0].speed, Speed::Multiplier(2.0));
+    assert_eq!(second_char.instances[0].scale, 1.5);
+
+    let mut library = reloaded;
+    library.remove_character(0);
+    let reloaded = CharacterLibrary::load_from(store);
+    assert_eq!(reloaded.characters.len(), 1);
+    assert_eq!(reloaded.characters[0].path, second);
+}
+
+#[test]
+fn fs_store_round_trip() {
+    let data = TempDir::new().unwrap();
+    let media = TempDir::new().unwrap();
+    round_trip(Arc::new(FsStore::new(data.path())), media.path());
+    assert!(data.path().join("library.json").exists());
+}
+
+#[test]
+fn memory_store_round_trip() {
+    let media = TempDir::new().unwrap();
+    round_trip(Arc::new(MemoryStore::new()), media.path());
+}
+
+#[test]
+fn changes_are_held_until_flushed() {
+    let media = TempDir::new().unwrap();
+    let store: Arc<dyn LibraryStore> = Arc::new(MemoryStore::new());
+    let path = write_png(media.path(), "walker.png", 2, 2);
+
+    let mut library = CharacterLibrary::load_from(store.clone());
+    let index = library.add_character(&path).unwrap();
+    let instance = first_instance(&library, index);
+    for x in 0..50 {
+        library.update_position(instance, [x as f32, 0.0]);
+    }
+    let on_disk = CharacterLibrary::load_from(store.clone());
+    assert_eq!(on_disk.characters[0].instances[0].window_pos, None);
+
+    library.flush();
+    let on_disk = CharacterLibrary::load_from(store);
+    assert_eq!(
+        on_disk.characters[0].instances[0].window_pos,
+        Some([49.0, 0.0])
+    );
+}
+
+#[test]
+fn each_instance_keeps_its_own_placement() {
+    let media = TempDir::new().unwrap();
+    let store: Arc<dyn LibraryStore> = Arc::new(MemoryStore::new());
+    let path = write_png(media.path(), "walker.png", 2, 2);
+
+    let mut library = CharacterLibrary::load_from(store.clone());
+    let index = library.add_character(&path).unwrap();
+    let first = first_instance(&library, index);
+    library.update_position(first, [10.0, 20.0]);
+    let second = library.add_instance(index).unwrap();
+    library.update_settings(second, Speed::Fps(12), 0.5);
+    library.flush();
+
+    let reloaded = CharacterLibrary::load_from(store);
+    let instances = &reloaded.characters[0].instances;
+    assert_eq!(instances.len(), 2);
+    assert_eq!(instances[0].scale, 1.0);
+    assert_eq!(instances[1].id, second);
+    assert!(instances[1].enabled);
+    assert_eq!(instances[1].window_pos, Some([50.0, 60.0]));
+    assert_eq!(instances[1].speed, Speed::Fps(12));
+
+    let mut library = reloaded;
+    assert!(library.remove_instance(second));
+    assert!(!library.remove_instance(first));
+    assert_eq!(library.characters[0].instances.len(), 1);
+}
+
+#[test]
+fn duplicate_add_is_rejected() {
+    let media = TempDir::new().unwrap();
+    let path = write_png(media.path(), "twice.png", 2, 2);
+    let mut library = CharacterLibrary::empty(Arc::new(MemoryStore::new()));
+    library.add_character(&path).unwrap();
+    assert!(library.add_character(&path).is_err());
+    assert_eq!(library.characters.len(), 1);
+}
+
+#[test]
+fn same_content_elsewhere_is_a_duplicate() {
+    let media = TempDir::new().unwrap();
+    let path = write_png(media.path(), "walker.png", 2, 2);
+    let copy = media.path().join("copy.png");
+    fs::copy(&path, &copy).unwrap();
+
+    let mut library = CharacterLibrary::empty(Arc::new(MemoryStore::new()));
+    library.add_character(&path).unwrap();
+    assert!(library.add_character(copy.to_str().unwrap()).is_err());
+    assert_eq!(library.characters.len(), 1);
+}
+
+#[test]
+fn unreadable_library_is_quarantined() {
+    let data = TempDir::new().unwrap();
+    fs::write(data.path().join("library.json"), "{\"characters\": [").unwrap();
+
+    let library = CharacterLibrary::load_from(Arc::new(FsStore::new(data.path())));
+    assert!(library.characters.is_empty());
+    assert!(library.load_warning.is_some());
+    let kept = fs::read_dir(data.path())
+        .unwrap()
+        .flatten()
+        .any(|e| e.file_name().to_string_lossy().contains(".corrupt-"));
+    assert!(kept);
+}
+
+#[test]
+fn unreadable_settings_are_quarantined() {
+    let data = TempDir::new().unwrap();
+    fs::write(data.path().join("settings.json"), "{\"speed\": ").unwrap();
+
+    let settings = AppSettings::load_from(Arc::new(FsStore::new(data.path())));
+    assert_eq!(settings.image_scale, AppSettings::default().image_scale);
+    assert!(settings.load_warning.is_some());
+    let kept = fs::read_dir(data.path()).unwrap().flatten().any(|e| {
+        e.file_name()
+            .to_string_lossy()
+            .starts_with("settings.json.corrupt-")
+    });
+    assert!(kept);
+}
+
+#[test]
+fn settings_that_cant_be_read_are_reported() {
+    let data = TempDir::new().unwrap();
+    // Reading a directory fails, as an unreadable file would.
+    fs::create_dir(data.path().join("settings.json")).unwrap();
+
+    let settings = AppSettings::load_from(Arc::new(FsStore::new(data.path())));
+    let warning = settings.load_warning.unwrap();
+    assert!(warning.contains("could not read it"));
+}
+
+#[test]
+fn unversioned_character_list_is_migrated() {
+    let store = Arc::new(MemoryStore::new());
+    store
+        .write(
+            "library.json",
+            br#"[{"name": "old", "path": "/tmp/old.webp", "enabled": true}]"#,
+        )
+        .unwrap();
+
+    let library = CharacterLibrary::load_from(store);
+    assert!(library.load_warning.is_none());
+    assert_eq!(library.characters.len(), 1);
+    assert_eq!(library.characters[0].name, "old");
+    assert!(library.characters[0].instances[0].enabled);
+}
+
+#[test]
+fn layouts_are_migrated_to_character_ids() {
+    let store = Arc::new(MemoryStore::new());
+    store
+        .write(
+            "library.json",
+            br#"{"version": 2, "characters": [{"name": "a", "path": "/tmp/a.webp"}],
+                "layouts": [{"name": "left", "entries": [
+                    {"path": "/tmp/a.webp", "scale": 2.0, "speed": 0},
+                    {"path": "/tmp/gone.webp", "scale": 1.0, "speed": 0}]}]}"#,
+        )
+        .unwrap();
+
+    let library = CharacterLibrary::load_from(store);
+    assert!(library.load_warning.is_none());
+    let entries = &library.layouts[0].entries;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].instance, library.characters[0].instances[0].id);
+    assert_eq!(entries[0].scale, 2.0);
+}
+
+#[test]
+fn framerate_speeds_are_migrated() {
+    let store = Arc::new(MemoryStore::new());
+    store
+        .write(
+            "library.json",
+            br#"{"version": 4, "characters": [
+                {"name": "a", "path": "/tmp/a.webp", "instances": [{"speed": 0}]},
+                {"name": "b", "path": "/tmp/b.webp", "instances": [{"speed": 24}]}]}"#,
+        )
+        .unwrap();
+
+    let library = CharacterLibrary::load_from(store);
+    assert!(library.load_warning.is_none());
+    assert_eq!(library.characters[0].instances[0].speed, Speed::default());
+    assert_eq!(library.characters[1].instances[0].speed, Speed::Fps(24));
+}
+
+#[test]
+fn backups_can_be_restored() {
+    let media = TempDir::new().unwrap();
+    let store: Arc<dyn LibraryStore> = Arc::new(MemoryStore::new());
+    let first = write_png(media.path(), "first.png", 2, 2);
+    let second = write_png(media.path(), "second.png", 3, 2);
+
+    let mut library = CharacterLibrary::load_from(store.clone());
+    library.add_character(&first).unwrap();
+    library.add_character(&second).unwrap();
+    assert_eq!(library.backups().len(), 1);
+
+    let restored = library.restore_backup(1).unwrap();
+    assert_eq!(restored.characters.len(), 1);
+    assert_eq!(restored.characters[0].path, first);
+}
+
+#[test]
+fn quick_saves_keep_a_single_backup() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("library.json");
+    for contents in ["1", "2", "3"] {
+        persist::write_atomic(&path, contents.as_bytes()).unwrap();
+    }
+    assert_eq!(persist::backups(&path).len(), 1);
+    let newest = persist::backup_path(&path, 1);
+    assert_eq!(fs::read_to_string(&newest).unwrap(), "1");
+
+    // A restore is always backed up, so it can be undone.
+    persist::restore_backup(&path, 1).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "1");
+    assert_eq!(fs::read_to_string(&newest).unwrap(), "3");
+}
+
+#[test]
+fn memory_store_backs_up_like_the_disk() {
+    let store = MemoryStore::new();
+    for contents in ["1", "2", "3"] {
+        store.write("library.json", contents.as_bytes()).unwrap();
+    }
+    assert_eq!(store.backups("library.json").len(), 1);
+
+    store.restore_backup("library.json", 1).unwrap();
+    assert_eq!(store.read("library.json").unwrap().unwrap(), b"1");
+    assert_eq!(store.backups("library.json").len(), 2);
+}
+
+#[test]
+fn unconfirmed_optimization_survives_a_reload() {
+    let media = TempDir::new().unwrap();
+    let store: Arc<dyn LibraryStore> = Arc::new(MemoryStore::new());
+    let original = write_png(media.path(), "big.png", 4, 4);
+    let smaller = write_png(media.path(), "small.png", 4, 4);
+
+    let mut library = CharacterLibrary::load_from(store.clone());
+    let index = library.add_character(&original).unwrap();
+    let character = &library.characters[index];
+    let entry = OptimizedEntry {
+        id: character.id,
+        name: character.name.clone(),
+        original_path: original.clone(),
+        new_path: smaller.clone(),
+        bytes_before: 200,
+        bytes_after: 100,
+        media_info: character.media_info.clone().unwrap(),
+        content_hash: None,
+    };
+    let report = OptimizeReport {
+        entries: vec![entry],
+        skipped: vec![],
+    };
+    library.apply_optimization(&report);
+
+    let mut reloaded = CharacterLibrary::load_from(store.clone());
+    assert_eq!(reloaded.characters[0].path, smaller);
+    assert_eq!(reloaded.pending_optimization.len(), 1);
+    assert_eq!(reloaded.pending_optimization[0].original_path, original);
+
+    reloaded.confirm_optimization();
+    let reloaded = CharacterLibrary::load_from(store);
+    assert!(reloaded.pending_optimization.is_empty());
+}
+
+#[test]
+fn paths_under_media_root_are_stored_relative() {
+    let media = TempDir::new().unwrap();
+    let path = write_png(media.path(), "walker.png", 2, 2);
+    let store: Arc<dyn LibraryStore> = Arc::new(MemoryStore::new());
+
+    let mut library = CharacterLibrary::empty(store.clone());
+    library.set_media_root(Some(media.path().to_string_lossy().to_string()));
+    library.add_character(&path).unwrap();
+
+    let raw: serde_json::Value =
+        serde_json::from_slice(&store.read("library.json").unwrap().unwrap()).unwrap();
+    assert_eq!(raw["characters"][0]["path"], "walker.png");
+    let reloaded = CharacterLibrary::load_from(store);
+    assert_eq!(reloaded.characters[0].path, path);
+}
+
+#[test]
+fn moved_file_is_found_by_content_hash() {
+    let media = TempDir::new().unwrap();
+    let moved = TempDir::new().unwrap();
+    let path = write_png(media.path(), "walker.png", 3, 2);
+    let mut library = CharacterLibrary::empty(Arc::new(MemoryStore::new()));
+    library.add_character(&path).unwrap();
+
+    let new_path = moved.path().join("renamed.png");
+    fs::rename(&path, &new_path).unwrap();
+    assert_eq!(library.missing_characters(), vec![0]);
+
+    let missing = vec![MissingFile::from_character(&library.characters[0])];
+    let report = relink::search(moved.path(), &missing, &Progress::new());
+    assert_eq!(report.matches.len(), 1);
+    assert_eq!(report.matches[0].kind, MatchKind::Hash);
+
+    assert_eq!(report.matches[0].id, library.characters[0].id);
+    assert!(library.relink(report.matches[0].id, &report.matches[0].new_path));
+    assert!(library.missing_characters().is_empty());
+    assert_eq!(library.characters[0].path, new_path.to_string_lossy());
+}
+
+#[test]
+fn relinking_to_other_content_updates_the_hash() {
+    let media = TempDir::new().unwrap();
+    let moved = TempDir::new().unwrap();
+    let path = write_png(media.path(), "walker.png", 3, 2);
+    let mut library = CharacterLibrary::empty(Arc::new(MemoryStore::new()));
+    library.add_character(&path).unwrap();
+    let old_hash = library.characters[0].content_hash.clone();
+    assert!(old_hash.is_some());
+
+    // Same name, different picture: only found by name.
+    fs::remove_file(&path).unwrap();
+    let other = write_png(moved.path(), "walker.png", 5, 5);
+    let missing = vec![MissingFile::from_character(&library.characters[0])];
+    let report = relink::search(moved.path(), &missing, &Progress::new());
+    assert_eq!(report.matches[0].kind, MatchKind::Name);
+
+    assert!(library.relink(report.matches[0].id, &other));
+    let relinked = &library.characters[0];
+    assert_ne!(relinked.content_hash, old_hash);
+    assert_eq!(relinked.media_info.as_ref().unwrap().width, 5);
+}
@@ -0,0 +1,73 @@
+use animamac::media_info;
+use image::{Delay, Frame, Rgba, RgbaImage};
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn gif_delays_come_from_its_headers() {
+    let media = TempDir::new().unwrap();
+    let path = media.path().join("blink.gif");
+    let frames = [70, 200, 70].map(|ms| {
+        let image = RgbaImage::from_pixel(6, 4, Rgba([255, 0, 0, 255]));
+        Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(ms, 1))
+    });
+    let mut encoder = image::codecs::gif::GifEncoder::new(fs::File::create(&path).unwrap());
+    encoder
+        .set_repeat(image::codecs::gif::Repeat::Infinite)
+        .unwrap();
+    encoder.encode_frames(frames).unwrap();
+    drop(encoder);
+
+    let info = media_info::probe(&path).unwrap();
+    assert_eq!((info.width, info.height), (6, 4));
+    assert_eq!(info.frame_delays_ms, [70, 200, 70]);
+    assert_eq!(info.loop_count, 0);
+}
+
+fn chunk(fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = fourcc.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+fn u24(value: u32) -> [u8; 3] {
+    let [a, b, c, _] = value.to_le_bytes();
+    [a, b, c]
+}
+
+#[test]
+fn webp_delays_come_from_its_frame_chunks() {
+    // Flags (animation and alpha), reserved, then canvas size minus one.
+    let mut vp8x = vec![0x12, 0, 0, 0];
+    vp8x.extend(u24(5));
+    vp8x.extend(u24(3));
+    // Background colour, then the loop count.
+    let anim = [0, 0, 0, 0, 2, 0];
+    let mut body = b"WEBP".to_vec();
+    body.extend(chunk(b"VP8X", &vp8x));
+    body.extend(chunk(b"ANIM", &anim));
+    for delay_ms in [50, 150] {
+        // Offset, size minus one, duration, flags; the frame data isn't read.
+        let mut anmf = [u24(0), u24(0), u24(5), u24(3), u24(delay_ms)].concat();
+        anmf.push(0);
+        anmf.extend(chunk(b"VP8L", &[0x2f, 0, 0, 0, 0]));
+        body.extend(chunk(b"ANMF", &anmf));
+    }
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend(body);
+
+    let media = TempDir::new().unwrap();
+    let path = media.path().join("blink.webp");
+    fs::write(&path, bytes).unwrap();
+
+    let info = media_info::probe(&path).unwrap();
+    assert_eq!((info.width, info.height), (6, 4));
+    assert_eq!(info.frame_delays_ms, [50, 150]);
+    assert_eq!(info.loop_count, 2);
+    assert!(info.has_alpha);
+}
@@ -0,0 +1,160 @@
+use animamac::character_lib::CharacterLibrary;
+use animamac::jobs::Progress;
+use animamac::pack::{self, PackError};
+use animamac::paths;
+use animamac::playback::Speed;
+use animamac::store::MemoryStore;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tempfile::TempDir;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+/// Imports unpack into the app's cache, so every test in this file shares a
+/// data directory of its own instead of the user's.
+fn isolate_data_dir() {
+    static HOME: OnceLock<TempDir> = OnceLock::new();
+    HOME.get_or_init(|| {
+        let home = TempDir::new().unwrap();
+        // SAFETY: set once, before anything in this process reads it.
+        unsafe { std::env::set_var(paths::HOME_ENV, home.path()) };
+        home
+    });
+}
+
+fn png_bytes(width: u32, height: u32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let image = image::RgbaImage::new(width, height);
+    image
+        .write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    bytes
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// A pack with `manifest` and the given media files.
+fn write_pack(dir: &Path, manifest: Value, media: &[(&str, &[u8])]) -> PathBuf {
+    let path = dir.join("test.animapack");
+    let mut zip = ZipWriter::new(File::create(&path).unwrap());
+    for (name, bytes) in media {
+        zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(bytes).unwrap();
+    }
+    zip.start_file("manifest.json", SimpleFileOptions::default())
+        .unwrap();
+    zip.write_all(manifest.to_string().as_bytes()).unwrap();
+    zip.finish().unwrap();
+    path
+}
+
+fn entry(name: &str, file: &str, sha256: &str) -> Value {
+    json!({ "name": name, "file": file, "sha256": sha256 })
+}
+
+#[test]
+fn media_not_matching_its_hash_is_rejected() {
+    isolate_data_dir();
+    let dir = TempDir::new().unwrap();
+    let media = png_bytes(2, 2);
+    let claimed = sha256(&png_bytes(3, 3));
+    let manifest = json!({
+        "version": 2,
+        "characters": [entry("walker", "media/walker.png", &claimed)],
+    });
+    let pack = write_pack(dir.path(), manifest, &[("media/walker.png", &media)]);
+
+    let result = pack::import_pack(&pack, &[], &Progress::new());
+    assert!(matches!(result, Err(PackError::CorruptMedia(name)) if name == "walker"));
+}
+
+#[test]
+fn hash_that_isnt_a_hash_is_rejected() {
+    isolate_data_dir();
+    let dir = TempDir::new().unwrap();
+    let media = png_bytes(2, 2);
+    // It becomes a file name in the cache, so it mustn't lead out of it.
+    let manifest = json!({
+        "version": 2,
+        "characters": [entry("walker", "media/walker.png", "../../escaped")],
+    });
+    let pack = write_pack(dir.path(), manifest, &[("media/walker.png", &media)]);
+
+    let result = pack::import_pack(&pack, &[], &Progress::new());
+    assert!(matches!(result, Err(PackError::InvalidManifest(_))));
+}
+
+#[test]
+fn version_1_speeds_are_upgraded() {
+    isolate_data_dir();
+    let dir = TempDir::new().unwrap();
+    let (slow, still) = (png_bytes(2, 2), png_bytes(4, 2));
+    let mut fixed = entry("fixed", "media/slow.png", &sha256(&slow));
+    fixed["speed"] = json!(24);
+    let mut own = entry("own timing", "media/still.png", &sha256(&still));
+    own["speed"] = json!(0);
+    let manifest = json!({ "version": 1, "characters": [fixed, own] });
+    let media: [(&str, &[u8]); 2] = [("media/slow.png", &slow), ("media/still.png", &still)];
+    let pack = write_pack(dir.path(), manifest, &media);
+
+    let import = pack::import_pack(&pack, &[], &Progress::new()).unwrap();
+    assert_eq!(import.characters.len(), 2);
+    assert_eq!(import.characters[0].speed, Speed::Fps(24));
+    assert_eq!(import.characters[1].speed, Speed::default());
+}
+
+#[test]
+fn media_already_there_is_skipped() {
+    isolate_data_dir();
+    let dir = TempDir::new().unwrap();
+    let (known, new) = (png_bytes(5, 5), png_bytes(6, 5));
+    let known_path = dir.path().join("known.png");
+    fs::write(&known_path, &known).unwrap();
+    let mut library = CharacterLibrary::empty(Arc::new(MemoryStore::new()));
+    library
+        .add_character(&known_path.to_string_lossy())
+        .unwrap();
+
+    let manifest = json!({
+        "version": 2,
+        "characters": [
+            entry("known", "media/known.png", &sha256(&known)),
+            entry("new", "media/new.png", &sha256(&new)),
+            entry("new again", "media/new.png", &sha256(&new)),
+        ],
+    });
+    let media: [(&str, &[u8]); 2] = [("media/known.png", &known), ("media/new.png", &new)];
+    let pack = write_pack(dir.path(), manifest, &media);
+
+    let import = pack::import_pack(&pack, &library.characters, &Progress::new()).unwrap();
+    let names: Vec<&str> = import.characters.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["new"]);
+    assert_eq!(import.duplicates, ["known", "new again"]);
+    assert_eq!(import.characters[0].content_hash, sha256(&new));
+}
+
+#[test]
+fn uppercase_hashes_are_accepted() {
+    isolate_data_dir();
+    let dir = TempDir::new().unwrap();
+    let media = png_bytes(7, 3);
+    let hash = sha256(&media);
+    let manifest = json!({
+        "version": 2,
+        "characters": [entry("shouty", "media/shouty.png", &hash.to_uppercase())],
+    });
+    let pack = write_pack(dir.path(), manifest, &[("media/shouty.png", &media)]);
+
+    let import = pack::import_pack(&pack, &[], &Progress::new()).unwrap();
+    assert_eq!(import.characters[0].content_hash, hash);
+    assert!(import.characters[0].path.contains(&hash));
+}
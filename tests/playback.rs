@@ -0,0 +1,126 @@
+use animamac::playback::{
+    self, AfterLoops, Direction, PlaybackOptions, Playhead, Shown, Speed, Timeline,
+};
+use std::time::Duration;
+
+#[test]
+fn multiplier_keeps_frame_rhythm() {
+    // A long hold on the second frame.
+    let delays = [100, 400, 100];
+    let ms = Duration::from_millis;
+
+    let normal = Speed::Multiplier(1.0);
+    assert_eq!(
+        playback::frame_at(&delays, normal, ms(150)),
+        Some((1, ms(350)))
+    );
+    assert_eq!(
+        playback::frame_at(&delays, normal, ms(650)),
+        Some((0, ms(50)))
+    );
+
+    let double = Speed::Multiplier(2.0);
+    assert_eq!(
+        playback::frame_at(&delays, double, ms(75)),
+        Some((1, ms(175)))
+    );
+
+    let fixed = Speed::Fps(10);
+    assert_eq!(
+        playback::frame_at(&delays, fixed, ms(150)),
+        Some((1, ms(50)))
+    );
+    assert_eq!(playback::frame_at(&[], normal, ms(150)), None);
+
+    // Zero delays are shown for 100 ms, as in browsers.
+    assert_eq!(
+        playback::frame_at(&[0, 100], normal, ms(50)),
+        Some((0, ms(50)))
+    );
+}
+
+#[test]
+fn ping_pong_range_plays_then_holds() {
+    let delays = [100; 6];
+    let ms = Duration::from_millis;
+    let options = PlaybackOptions {
+        direction: Direction::PingPong,
+        range: Some([1, 3]),
+        loops: Some(2),
+        ..Default::default()
+    };
+    let timeline = Timeline::new(&delays, Speed::default(), &options).unwrap();
+    let frames: Vec<usize> = (0..4).map(|i| timeline.frame_at(ms(i * 100)).0).collect();
+    assert_eq!(frames, [1, 2, 3, 2]);
+
+    let mut playhead = Playhead::new(0);
+    playhead.tick(ms(0));
+    playhead.tick(ms(750));
+    assert_eq!(
+        playhead.shown(&timeline, &options),
+        Shown::Frame {
+            index: 2,
+            until_next: Some(ms(50))
+        }
+    );
+    playhead.tick(ms(800));
+    assert_eq!(
+        playhead.shown(&timeline, &options),
+        Shown::Frame {
+            index: 2,
+            until_next: None
+        }
+    );
+
+    let hide = PlaybackOptions {
+        after_loops: AfterLoops::Hide,
+        ..options
+    };
+    assert_eq!(playhead.shown(&timeline, &hide), Shown::Nothing);
+    playhead.restart();
+    assert!(matches!(
+        playhead.shown(&timeline, &hide),
+        Shown::Frame { index: 1, .. }
+    ));
+}
+
+#[test]
+fn paused_playhead_steps_one_frame() {
+    let delays = [100, 200, 100];
+    let ms = Duration::from_millis;
+    let options = PlaybackOptions::default();
+    let timeline = Timeline::new(&delays, Speed::default(), &options).unwrap();
+
+    let mut playhead = Playhead::new(0);
+    playhead.tick(ms(0));
+    playhead.tick(ms(50));
+    playhead.step(&timeline, &options);
+    assert!(playhead.is_paused());
+    playhead.tick(ms(5000));
+    assert_eq!(
+        playhead.shown(&timeline, &options),
+        Shown::Frame {
+            index: 1,
+            until_next: None
+        }
+    );
+}
+
+#[test]
+fn apng_short_delays_play_like_gifs() {
+    let media = tempfile::TempDir::new().unwrap();
+    let path = media.path().join("fast.png");
+    let file = std::fs::File::create(&path).unwrap();
+    let mut encoder = png::Encoder::new(file, 1, 1);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_animated(2, 0).unwrap();
+    encoder.set_frame_delay(0, 100).unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&[0, 0, 0, 255]).unwrap();
+    writer.write_image_data(&[255, 255, 255, 255]).unwrap();
+    writer.finish().unwrap();
+
+    let animation = animamac::apng::decode_apng(&path).unwrap();
+    let delays: Vec<u32> = animation.frames.iter().map(|f| f.delay_ms).collect();
+    assert_eq!(delays, [100, 100]);
+}
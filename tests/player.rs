@@ -0,0 +1,48 @@
+use animamac::player::{FrameCache, FrameDecoder};
+use std::fs;
+use tempfile::TempDir;
+use uuid::Uuid;
+
+#[test]
+fn frames_are_decoded_in_order_and_rewound() {
+    let media = TempDir::new().unwrap();
+    let path = media.path().join("blink.gif");
+    let frames = (0..3u8).map(|shade| {
+        let image = image::RgbaImage::from_pixel(2, 2, image::Rgba([shade, 0, 0, 255]));
+        image::Frame::new(image)
+    });
+    let mut encoder = image::codecs::gif::GifEncoder::new(fs::File::create(&path).unwrap());
+    encoder.encode_frames(frames).unwrap();
+    drop(encoder);
+
+    let mut decoder = FrameDecoder::new(&path);
+    for expected in 0..3 {
+        let (index, image) = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(index, expected);
+        assert_eq!(image.get_pixel(0, 0).0[0], expected as u8);
+    }
+    assert!(decoder.next_frame().unwrap().is_none());
+    assert_eq!(decoder.frame_count(), Some(3));
+    assert_eq!(decoder.position(), 0);
+    assert_eq!(decoder.next_frame().unwrap().unwrap().0, 0);
+}
+
+#[test]
+fn frame_cache_evicts_least_recently_used() {
+    let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut cache = FrameCache::new(100);
+    cache.insert(a, 0, "a0", 40);
+    cache.insert(a, 1, "a1", 40);
+    assert_eq!(cache.get(a, 0), Some(&"a0"));
+    cache.insert(b, 0, "b0", 40);
+
+    assert!(cache.contains(a, 0));
+    assert!(!cache.contains(a, 1));
+    assert_eq!(cache.asset_bytes(a), 40);
+    assert_eq!(cache.used_bytes(), 80);
+
+    cache.remove_asset(a);
+    assert_eq!(cache.used_bytes(), 40);
+    cache.set_budget(10);
+    assert_eq!(cache.used_bytes(), 0);
+}
@@ -0,0 +1,18 @@
+use animamac::redraw::{self, POWER_SAVER_FPS_CAP};
+use animamac::settings::AppSettings;
+use std::time::Duration;
+
+#[test]
+fn power_saver_caps_redraws() {
+    let mut settings = AppSettings::default();
+    assert_eq!(settings.effective_fps_cap(), Some(redraw::DEFAULT_FPS_CAP));
+    settings.fps_cap = None;
+    assert_eq!(settings.effective_fps_cap(), None);
+    settings.power_saver = true;
+    assert_eq!(settings.effective_fps_cap(), Some(POWER_SAVER_FPS_CAP));
+
+    let ms = Duration::from_millis;
+    assert_eq!(redraw::paced(ms(10), Some(20)), ms(50));
+    assert_eq!(redraw::paced(ms(80), Some(20)), ms(80));
+    assert_eq!(redraw::paced(ms(10), None), ms(10));
+}
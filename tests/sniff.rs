@@ -0,0 +1,76 @@
+use animamac::sniff::{MediaFormat, sniff_bytes, sniff_file};
+use std::fs;
+use tempfile::TempDir;
+
+/// A PNG chunk; the sniffer only reads headers, so the CRC is left zero.
+fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut bytes = (data.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(kind);
+    bytes.extend_from_slice(data);
+    bytes.extend_from_slice(&[0; 4]);
+    bytes
+}
+
+fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+    let mut bytes = vec![0, 0, 0, 0x18];
+    bytes.extend_from_slice(b"ftyp");
+    bytes.extend_from_slice(brand);
+    bytes.extend_from_slice(&[0; 12]);
+    bytes
+}
+
+#[test]
+fn only_video_brands_are_mp4() {
+    let format = |brand| sniff_bytes(&ftyp(brand)).map(|s| s.format);
+    assert_eq!(format(b"isom"), Some(MediaFormat::Mp4));
+    assert_eq!(format(b"M4V "), Some(MediaFormat::Mp4));
+    assert_eq!(format(b"qt  "), Some(MediaFormat::Mov));
+    assert_eq!(format(b"avif"), None);
+    assert_eq!(format(b"heic"), None);
+}
+
+#[test]
+fn animation_is_found_past_the_head() {
+    let media = TempDir::new().unwrap();
+    let path = media.path().join("blink.gif");
+    // Big enough frames that the second image descriptor is well past the
+    // bytes used to identify the format.
+    let frames = (0..2u8).map(|shade| {
+        let image = image::RgbaImage::from_fn(64, 64, |x, y| {
+            image::Rgba([(x * 4) as u8 ^ shade, (y * 4) as u8, shade, 255])
+        });
+        image::Frame::new(image)
+    });
+    let mut encoder = image::codecs::gif::GifEncoder::new(fs::File::create(&path).unwrap());
+    encoder.encode_frames(frames).unwrap();
+    drop(encoder);
+
+    let sniffed = sniff_file(&path).unwrap().unwrap();
+    assert_eq!(sniffed.format, MediaFormat::Gif);
+    assert!(sniffed.animated);
+
+    let still = media.path().join("still.png");
+    image::RgbaImage::new(4, 4).save(&still).unwrap();
+    let sniffed = sniff_file(&still).unwrap().unwrap();
+    assert_eq!(sniffed.format, MediaFormat::Png);
+    assert!(!sniffed.animated);
+}
+
+#[test]
+fn apng_is_found_past_ancillary_chunks() {
+    let media = TempDir::new().unwrap();
+    let path = media.path().join("wave.png");
+    let mut bytes = b"\x89PNG\r\n\x1a\n".to_vec();
+    bytes.extend(chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]));
+    // Metadata such as an ICC profile often comes before acTL.
+    bytes.extend(chunk(b"iCCP", &[0; 4096]));
+    bytes.extend(chunk(b"tEXt", b"Software\0editor"));
+    bytes.extend(chunk(b"acTL", &[0, 0, 0, 2, 0, 0, 0, 0]));
+    bytes.extend(chunk(b"IDAT", &[0; 16]));
+    bytes.extend(chunk(b"IEND", &[]));
+    fs::write(&path, bytes).unwrap();
+
+    let sniffed = sniff_file(&path).unwrap().unwrap();
+    assert_eq!(sniffed.format, MediaFormat::Apng);
+    assert!(sniffed.animated);
+}